@@ -1,520 +1,4247 @@
 pub mod utils;
 
 use {
-    crate::utils::{assert_initialized, assert_owned_by, spl_token_transfer, TokenTransferParams},
+    crate::utils::{
+        assert_allowlisted, assert_holds_verified_nft, assert_initialized, assert_owned_by,
+        close_program_account, create_associated_token_account, create_or_allocate_account_raw,
+        get_associated_token_address, get_ed25519_message, spl_token_burn, spl_token_close_account,
+        spl_token_mint_to, spl_token_transfer, TokenBurnParams, TokenCloseParams,
+        TokenMintToParams, TokenTransferParams, ASSOCIATED_TOKEN_PROGRAM_ID,
+    },
     anchor_lang::{
-        prelude::*, solana_program::system_program, AnchorDeserialize, AnchorSerialize,
-        Discriminator, Key,
+        prelude::*,
+        solana_program::{
+            keccak,
+            program::{invoke, invoke_signed},
+            program_memory::sol_memset,
+            system_instruction, system_program, sysvar,
+        },
+        AnchorDeserialize, AnchorSerialize, Discriminator, Key,
     },
     arrayref::array_ref,
     spl_token::state::{Account, Mint},
     spl_token_metadata::{
-        instruction::{create_master_edition, create_metadata_accounts, update_metadata_accounts},
+        instruction::{
+            create_master_edition, create_metadata_accounts,
+            mint_new_edition_from_master_edition_via_token, update_metadata_accounts,
+        },
         state::{
-            MAX_CREATOR_LEN, MAX_CREATOR_LIMIT, MAX_NAME_LENGTH, MAX_SYMBOL_LENGTH, MAX_URI_LENGTH,
+            get_master_edition, Data, Metadata, MAX_CREATOR_LEN, MAX_CREATOR_LIMIT,
+            MAX_NAME_LENGTH, MAX_SYMBOL_LENGTH, MAX_URI_LENGTH,
         },
     },
     std::cell::Ref,
 };
 
 const PREFIX: &str = "nft_vault";
+const RECEIPT: &str = "receipt";
+const RAFFLE: &str = "raffle";
+const TICKET: &str = "ticket";
+const NONCE: &str = "nonce";
+/// Slots between the first draw_raffle call and the slot whose hash seeds the draw.
+const RAFFLE_DRAW_DELAY: u64 = 8;
 #[program]
 pub mod nft_nft_vault {
-    use anchor_lang::solana_program::{
-        program::{invoke, invoke_signed},
-        system_instruction,
-    };
+    use anchor_lang::solana_program::program_memory::sol_memset;
 
     use super::*;
 
-    pub fn mint_nft<'info>(ctx: Context<'_, '_, '_, 'info, MintNFT<'info>>) -> ProgramResult {
-        let nft_vault = &mut ctx.accounts.nft_vault;
-        let config = &ctx.accounts.config;
+    /// Mints the next item. With a `recipient`, the program creates the recipient's
+    /// associated token account and mints the token there instead of expecting the payer
+    /// to have minted it beforehand. With a bot tax, some failed mints only charge the tax.
+    ///
+    /// The allowlist, wallet cap and token gate apply to the wallet that receives the item:
+    /// the recipient when there is one, the payer otherwise. The cosigner approves the
+    /// payer, as the one sending the mint.
+    ///
+    /// Accounts only some vaults need come first in remaining accounts, and only when the
+    /// vault uses them: the CosignNonce and the instructions sysvar with a cosigner, the
+    /// WalletMintCounter while a wallet cap applies, then the recipient, its associated
+    /// token account and the associated token program with a `recipient`, the MintReceipt
+    /// when the vault records receipts, the instructions sysvar when the vault draws its
+    /// config lines at random, and every config page, writable and in page order, when the
+    /// vault mints config lines. The payment accounts follow, and print vaults end with
+    /// their print accounts.
+    pub fn mint_nft<'info>(
+        ctx: Context<'_, '_, '_, 'info, MintNFT<'info>>,
+        proof: Option<Vec<[u8; 32]>>,
+        recipient: Option<Pubkey>,
+    ) -> ProgramResult {
         let clock = &ctx.accounts.clock;
+        let minter = recipient.unwrap_or(*ctx.accounts.payer.key);
 
-        match nft_vault.data.go_live_date {
-            None => {
-                if *ctx.accounts.payer.key != nft_vault.authority {
-                    return Err(ErrorCode::NftVaultNotLiveYet.into());
-                }
-            }
-            Some(val) => {
-                if clock.unix_timestamp < val {
-                    if *ctx.accounts.payer.key != nft_vault.authority {
-                        return Err(ErrorCode::NftVaultNotLiveYet.into());
-                    }
-                }
-            }
-        }
+        let mut remaining_accounts = ctx.remaining_accounts;
+        let cosign_accounts = match ctx.accounts.nft_vault.data.cosigner {
+            Some(_) => take_accounts(&mut remaining_accounts, 2, ErrorCode::CosignAccountsMissing)?,
+            None => &[],
+        };
+        let wallet_mint_limit =
+            get_wallet_mint_limit(&ctx.accounts.nft_vault.data, clock.unix_timestamp);
+        let counter = match wallet_mint_limit {
+            Some(_) => take_accounts(
+                &mut remaining_accounts,
+                1,
+                ErrorCode::WalletMintCounterMissing,
+            )?,
+            None => &[],
+        };
+        let recipient_accounts = match recipient {
+            Some(_) => take_accounts(
+                &mut remaining_accounts,
+                3,
+                ErrorCode::RecipientAccountsMissing,
+            )?,
+            None => &[],
+        };
+        let receipt = take_receipt_account(&ctx.accounts.nft_vault, &mut remaining_accounts)?;
+        let instruction_sysvar = take_draw_account(
+            &ctx.accounts.nft_vault,
+            &ctx.accounts.config,
+            &mut remaining_accounts,
+        )?;
+        let config_pages = take_config_pages(
+            &ctx.accounts.nft_vault,
+            &ctx.accounts.config,
+            &mut remaining_accounts,
+        )?;
+        let (remaining_accounts, edition_marker) =
+            split_edition_marker(&ctx.accounts.nft_vault, remaining_accounts)?;
+        let (remaining_accounts, print_accounts) =
+            split_print_accounts(&ctx.accounts.nft_vault, remaining_accounts)?;
 
-        if nft_vault.items_redeemed >= nft_vault.data.items_available {
-            return Err(ErrorCode::NftVaultEmpty.into());
+        // Every failure the bot tax covers is checked before the cosigner nonce, the wallet
+        // counter or anything else records the mint
+        if let Err(err) = assert_mint_allowed(
+            &ctx.accounts.nft_vault,
+            &minter,
+            clock.unix_timestamp,
+            &proof,
+        )
+        .and_then(|_| assert_public_supply(&ctx.accounts.nft_vault, 1))
+        .and_then(|_| match ctx.accounts.nft_vault.raffle {
+            Some(_) => Ok(()),
+            None => assert_payment_mint(&ctx.accounts.nft_vault, remaining_accounts),
+        }) {
+            return charge_bot_tax(BotTaxParams {
+                nft_vault: &ctx.accounts.nft_vault,
+                payer: ctx.accounts.payer.clone(),
+                wallet: ctx.accounts.wallet.clone(),
+                system_program: ctx.accounts.system_program.clone(),
+                error: err,
+            });
         }
 
-        if let Some(mint) = nft_vault.token_mint {
-            let token_account_info = &ctx.remaining_accounts[0];
-            let transfer_authority_info = &ctx.remaining_accounts[1];
-            let token_account: Account = assert_initialized(&token_account_info)?;
-
-            assert_owned_by(&token_account_info, &spl_token::id())?;
+        if let Some(cosigner) = ctx.accounts.nft_vault.data.cosigner {
+            assert_cosigned(CosignParams {
+                program_id: ctx.program_id,
+                nft_vault_key: ctx.accounts.nft_vault.key(),
+                cosigner,
+                nonce_account: cosign_accounts[0].clone(),
+                payer: ctx.accounts.payer.clone(),
+                instruction_sysvar_account: cosign_accounts[1].clone(),
+                rent: ctx.accounts.rent.to_account_info(),
+                system_program: ctx.accounts.system_program.clone(),
+                unix_timestamp: clock.unix_timestamp,
+            })?;
+        }
 
-            if token_account.mint != mint {
-                return Err(ErrorCode::MintMismatch.into());
-            }
+        if let Some((max_per_wallet, phase)) = wallet_mint_limit {
+            record_wallet_mints(WalletMintParams {
+                program_id: ctx.program_id,
+                nft_vault_key: ctx.accounts.nft_vault.key(),
+                max_per_wallet,
+                phase,
+                count: 1,
+                counter: counter[0].clone(),
+                minter,
+                payer: ctx.accounts.payer.clone(),
+                rent: ctx.accounts.rent.to_account_info(),
+                system_program: ctx.accounts.system_program.clone(),
+            })?;
+        }
 
-            if token_account.amount < nft_vault.data.price {
-                return Err(ErrorCode::NotEnoughTokens.into());
-            }
+        let (price, payment_mint) = match ctx.accounts.nft_vault.raffle {
+            // Raffle winners already paid for their ticket
+            Some(raffle_key) => redeem_raffle_ticket(RedeemTicketParams {
+                program_id: ctx.program_id,
+                nft_vault: &ctx.accounts.nft_vault,
+                config: &ctx.accounts.config,
+                raffle_key,
+                minter,
+                wallet: ctx.accounts.wallet.clone(),
+                token_program: ctx.accounts.token_program.clone(),
+                remaining_accounts,
+            })?,
+            None => charge_for_mint(ChargeParams {
+                nft_vault: &ctx.accounts.nft_vault,
+                config: &ctx.accounts.config,
+                minter,
+                payer: ctx.accounts.payer.clone(),
+                wallet: ctx.accounts.wallet.clone(),
+                token_program: ctx.accounts.token_program.clone(),
+                system_program: ctx.accounts.system_program.clone(),
+                remaining_accounts,
+                unix_timestamp: clock.unix_timestamp,
+                count: 1,
+            })?,
+        };
 
-            spl_token_transfer(TokenTransferParams {
-                source: token_account_info.clone(),
-                destination: ctx.accounts.wallet.clone(),
-                authority: transfer_authority_info.clone(),
-                authority_signer_seeds: &[],
+        if let Some(recipient) = recipient {
+            mint_to_recipient(RecipientMintParams {
+                recipient,
+                recipient_info: recipient_accounts[0].clone(),
+                recipient_token_account: recipient_accounts[1].clone(),
+                mint: ctx.accounts.mint.clone(),
+                mint_authority: ctx.accounts.mint_authority.clone(),
+                payer: ctx.accounts.payer.clone(),
                 token_program: ctx.accounts.token_program.clone(),
-                amount: nft_vault.data.price,
+                associated_token_program: recipient_accounts[2].clone(),
+                system_program: ctx.accounts.system_program.clone(),
+                rent: ctx.accounts.rent.to_account_info(),
             })?;
-        } else {
-            if ctx.accounts.payer.lamports() < nft_vault.data.price {
-                return Err(ErrorCode::NotEnoughSOL.into());
-            }
-
-            invoke(
-                &system_instruction::transfer(
-                    &ctx.accounts.payer.key,
-                    ctx.accounts.wallet.key,
-                    nft_vault.data.price,
-                ),
-                &[
-                    ctx.accounts.payer.clone(),
-                    ctx.accounts.wallet.clone(),
-                    ctx.accounts.system_program.clone(),
-                ],
-            )?;
         }
 
-        let config_line = get_config_line(
-            &config.to_account_info(),
-            nft_vault.items_redeemed as usize,
-        )?;
-
-        nft_vault.items_redeemed = nft_vault
-            .items_redeemed
-            .checked_add(1)
-            .ok_or(ErrorCode::NumericalOverflowError)?;
+        let config_info = ctx.accounts.config.to_account_info();
+        let nft_vault_info = ctx.accounts.nft_vault.to_account_info();
+        mint_item(MintItemParams {
+            program_id: ctx.program_id,
+            config: &ctx.accounts.config,
+            config_info,
+            config_pages,
+            print_accounts,
+            nft_vault: &mut ctx.accounts.nft_vault,
+            nft_vault_info,
+            metadata: ctx.accounts.metadata.clone(),
+            mint: ctx.accounts.mint.clone(),
+            mint_authority: ctx.accounts.mint_authority.clone(),
+            update_authority: ctx.accounts.update_authority.clone(),
+            master_edition: ctx.accounts.master_edition.clone(),
+            payer: ctx.accounts.payer.clone(),
+            token_metadata_program: ctx.accounts.token_metadata_program.clone(),
+            token_program: ctx.accounts.token_program.clone(),
+            system_program: ctx.accounts.system_program.clone(),
+            rent: ctx.accounts.rent.to_account_info(),
+            recent_slothashes: ctx.accounts.recent_slothashes.clone(),
+            instruction_sysvar,
+            edition_marker,
+            receipt,
+            price,
+            payment_mint,
+            unix_timestamp: clock.unix_timestamp,
+        })?;
 
-        let config_key = config.key();
-        let authority_seeds = [
-            PREFIX.as_bytes(),
-            config_key.as_ref(),
-            nft_vault.data.uuid.as_bytes(),
-            &[nft_vault.bump],
-        ];
+        Ok(())
+    }
 
-        let mut creators: Vec<spl_token_metadata::state::Creator> =
-            vec![spl_token_metadata::state::Creator {
-                address: nft_vault.key(),
-                verified: true,
-                share: 0,
-            }];
+    /// Mints `count` items in one instruction. The metadata, mint, master edition, then the
+    /// receipt when the vault records receipts and the edition marker of a print vault, of
+    /// each item are the last remaining accounts, after the accounts mint_nft takes there
+    /// in the same order.
+    pub fn mint_nft_batch<'info>(
+        ctx: Context<'_, '_, '_, 'info, MintNFTBatch<'info>>,
+        proof: Option<Vec<[u8; 32]>>,
+        count: u8,
+    ) -> ProgramResult {
+        let clock = &ctx.accounts.clock;
 
-        for c in &config.data.creators {
-            creators.push(spl_token_metadata::state::Creator {
-                address: c.address,
-                verified: false,
-                share: c.share,
-            });
+        if count == 0 {
+            return Err(ErrorCode::InvalidBatchSize.into());
         }
 
-        let metadata_infos = vec![
-            ctx.accounts.metadata.clone(),
-            ctx.accounts.mint.clone(),
-            ctx.accounts.mint_authority.clone(),
-            ctx.accounts.payer.clone(),
-            ctx.accounts.token_metadata_program.clone(),
-            ctx.accounts.token_program.clone(),
-            ctx.accounts.system_program.clone(),
-            ctx.accounts.rent.to_account_info().clone(),
-            nft_vault.to_account_info().clone(),
-        ];
+        if ctx.accounts.nft_vault.raffle.is_some() {
+            return Err(ErrorCode::RaffleRequiresSingleMint.into());
+        }
 
-        let master_edition_infos = vec![
-            ctx.accounts.master_edition.clone(),
-            ctx.accounts.mint.clone(),
-            ctx.accounts.mint_authority.clone(),
-            ctx.accounts.payer.clone(),
-            ctx.accounts.metadata.clone(),
-            ctx.accounts.token_metadata_program.clone(),
-            ctx.accounts.token_program.clone(),
-            ctx.accounts.system_program.clone(),
-            ctx.accounts.rent.to_account_info().clone(),
-            nft_vault.to_account_info().clone(),
-        ];
+        if ctx.accounts.nft_vault.data.cosigner.is_some() {
+            return Err(ErrorCode::CosignerRequiresSingleMint.into());
+        }
 
-        invoke_signed(
-            &create_metadata_accounts(
-                *ctx.accounts.token_metadata_program.key,
-                *ctx.accounts.metadata.key,
-                *ctx.accounts.mint.key,
-                *ctx.accounts.mint_authority.key,
-                *ctx.accounts.payer.key,
-                nft_vault.key(),
-                config_line.name,
-                config.data.symbol.clone(),
-                config_line.uri,
-                Some(creators),
-                config.data.seller_fee_basis_points,
-                false,
-                config.data.is_mutable,
-            ),
-            metadata_infos.as_slice(),
-            &[&authority_seeds],
-        )?;
+        let record_receipts = ctx.accounts.nft_vault.data.record_receipts;
+        let prints = ctx.accounts.nft_vault.data.print_master_mint.is_some();
+        let item_len = 3 + record_receipts as usize + prints as usize;
+        let item_accounts_len = item_len * count as usize;
+        if ctx.remaining_accounts.len() < item_accounts_len {
+            return Err(ErrorCode::InvalidBatchSize.into());
+        }
+        let (mut remaining_accounts, item_accounts) = ctx
+            .remaining_accounts
+            .split_at(ctx.remaining_accounts.len() - item_accounts_len);
 
-        invoke_signed(
-            &create_master_edition(
-                *ctx.accounts.token_metadata_program.key,
-                *ctx.accounts.master_edition.key,
-                *ctx.accounts.mint.key,
-                nft_vault.key(),
-                *ctx.accounts.mint_authority.key,
-                *ctx.accounts.metadata.key,
-                *ctx.accounts.payer.key,
-                Some(config.data.max_supply),
-            ),
-            master_edition_infos.as_slice(),
-            &[&authority_seeds],
+        assert_mint_allowed(
+            &ctx.accounts.nft_vault,
+            ctx.accounts.payer.key,
+            clock.unix_timestamp,
+            &proof,
         )?;
 
-        let mut new_update_authority = Some(nft_vault.authority);
+        assert_public_supply(&ctx.accounts.nft_vault, count as u64)?;
 
-        if !ctx.accounts.config.data.retain_authority {
-            new_update_authority = Some(ctx.accounts.update_authority.key());
+        if let Some((max_per_wallet, phase)) =
+            get_wallet_mint_limit(&ctx.accounts.nft_vault.data, clock.unix_timestamp)
+        {
+            let counter = take_accounts(
+                &mut remaining_accounts,
+                1,
+                ErrorCode::WalletMintCounterMissing,
+            )?;
+            record_wallet_mints(WalletMintParams {
+                program_id: ctx.program_id,
+                nft_vault_key: ctx.accounts.nft_vault.key(),
+                max_per_wallet,
+                phase,
+                count: count as u64,
+                counter: counter[0].clone(),
+                minter: *ctx.accounts.payer.key,
+                payer: ctx.accounts.payer.clone(),
+                rent: ctx.accounts.rent.to_account_info(),
+                system_program: ctx.accounts.system_program.clone(),
+            })?;
         }
 
-        invoke_signed(
-            &update_metadata_accounts(
-                *ctx.accounts.token_metadata_program.key,
-                *ctx.accounts.metadata.key,
-                nft_vault.key(),
-                new_update_authority,
-                None,
-                Some(true),
-            ),
-            &[
-                ctx.accounts.token_metadata_program.clone(),
-                ctx.accounts.metadata.clone(),
-                nft_vault.to_account_info().clone(),
-            ],
-            &[&authority_seeds],
+        let instruction_sysvar = take_draw_account(
+            &ctx.accounts.nft_vault,
+            &ctx.accounts.config,
+            &mut remaining_accounts,
+        )?;
+        let config_pages = take_config_pages(
+            &ctx.accounts.nft_vault,
+            &ctx.accounts.config,
+            &mut remaining_accounts,
         )?;
+        let (remaining_accounts, print_accounts) =
+            split_print_accounts(&ctx.accounts.nft_vault, remaining_accounts)?;
+
+        let (price, payment_mint) = charge_for_mint(ChargeParams {
+            nft_vault: &ctx.accounts.nft_vault,
+            config: &ctx.accounts.config,
+            minter: *ctx.accounts.payer.key,
+            payer: ctx.accounts.payer.clone(),
+            wallet: ctx.accounts.wallet.clone(),
+            token_program: ctx.accounts.token_program.clone(),
+            system_program: ctx.accounts.system_program.clone(),
+            remaining_accounts,
+            unix_timestamp: clock.unix_timestamp,
+            count: count as u64,
+        })?;
+
+        let config_info = ctx.accounts.config.to_account_info();
+        let nft_vault_info = ctx.accounts.nft_vault.to_account_info();
+        for item in item_accounts.chunks(item_len) {
+            mint_item(MintItemParams {
+                program_id: ctx.program_id,
+                config: &ctx.accounts.config,
+                config_info: config_info.clone(),
+                config_pages,
+                print_accounts,
+                nft_vault: &mut ctx.accounts.nft_vault,
+                nft_vault_info: nft_vault_info.clone(),
+                metadata: item[0].clone(),
+                mint: item[1].clone(),
+                mint_authority: ctx.accounts.mint_authority.clone(),
+                update_authority: ctx.accounts.update_authority.clone(),
+                master_edition: item[2].clone(),
+                payer: ctx.accounts.payer.clone(),
+                token_metadata_program: ctx.accounts.token_metadata_program.clone(),
+                token_program: ctx.accounts.token_program.clone(),
+                system_program: ctx.accounts.system_program.clone(),
+                rent: ctx.accounts.rent.to_account_info(),
+                recent_slothashes: ctx.accounts.recent_slothashes.clone(),
+                instruction_sysvar: instruction_sysvar.clone(),
+                edition_marker: if prints { item.last().cloned() } else { None },
+                receipt: if record_receipts {
+                    item.get(3).cloned()
+                } else {
+                    None
+                },
+                price,
+                payment_mint,
+                unix_timestamp: clock.unix_timestamp,
+            })?;
+        }
 
         Ok(())
     }
 
-    pub fn update_nft_vault(
-        ctx: Context<UpdateNftVault>,
-        price: Option<u64>,
-        go_live_date: Option<i64>,
+    /// Burns an nft verified by the vault's burn to mint creator and mints the next
+    /// config line in exchange, without charging the price. The wallet cap and token gate
+    /// still apply, and remaining accounts follow mint_nft: the cosign accounts, wallet
+    /// mint counter, receipt, instructions sysvar, config pages and token gate accounts
+    /// when the vault uses them, then the print accounts.
+    pub fn burn_to_mint<'info>(
+        ctx: Context<'_, '_, '_, 'info, BurnToMint<'info>>,
+        proof: Option<Vec<[u8; 32]>>,
     ) -> ProgramResult {
-        let nft_vault = &mut ctx.accounts.nft_vault;
+        let clock = &ctx.accounts.clock;
 
-        if let Some(p) = price {
-            nft_vault.data.price = p;
-        }
+        let creator = match ctx.accounts.nft_vault.data.burn_to_mint_creator {
+            Some(val) => val,
+            None => return Err(ErrorCode::BurnToMintNotEnabled.into()),
+        };
 
-        if let Some(go_l) = go_live_date {
-            msg!("Go live date changed to {}", go_l);
-            nft_vault.data.go_live_date = Some(go_l)
-        }
-        Ok(())
-    }
+        assert_mint_allowed(
+            &ctx.accounts.nft_vault,
+            ctx.accounts.payer.key,
+            clock.unix_timestamp,
+            &proof,
+        )?;
 
-    pub fn initialize_config(ctx: Context<InitializeConfig>, data: ConfigData) -> ProgramResult {
-        let config_info = &mut ctx.accounts.config;
-        if data.uuid.len() != 6 {
-            return Err(ErrorCode::UuidMustBeExactly6Length.into());
-        }
+        assert_public_supply(&ctx.accounts.nft_vault, 1)?;
 
-        let mut config = Config {
-            data,
-            authority: *ctx.accounts.authority.key,
+        let mut remaining_accounts = ctx.remaining_accounts;
+        let cosign_accounts = match ctx.accounts.nft_vault.data.cosigner {
+            Some(_) => take_accounts(&mut remaining_accounts, 2, ErrorCode::CosignAccountsMissing)?,
+            None => &[],
+        };
+        let wallet_mint_limit =
+            get_wallet_mint_limit(&ctx.accounts.nft_vault.data, clock.unix_timestamp);
+        let counter = match wallet_mint_limit {
+            Some(_) => take_accounts(
+                &mut remaining_accounts,
+                1,
+                ErrorCode::WalletMintCounterMissing,
+            )?,
+            None => &[],
         };
+        let receipt = take_receipt_account(&ctx.accounts.nft_vault, &mut remaining_accounts)?;
+        let instruction_sysvar = take_draw_account(
+            &ctx.accounts.nft_vault,
+            &ctx.accounts.config,
+            &mut remaining_accounts,
+        )?;
+        let config_pages = take_config_pages(
+            &ctx.accounts.nft_vault,
+            &ctx.accounts.config,
+            &mut remaining_accounts,
+        )?;
+        let gate_accounts = match ctx.accounts.nft_vault.data.token_gate_creator {
+            Some(_) => take_accounts(
+                &mut remaining_accounts,
+                2,
+                ErrorCode::TokenGateAccountsMissing,
+            )?,
+            None => &[],
+        };
+        let (remaining_accounts, edition_marker) =
+            split_edition_marker(&ctx.accounts.nft_vault, remaining_accounts)?;
+        let (_, print_accounts) =
+            split_print_accounts(&ctx.accounts.nft_vault, remaining_accounts)?;
 
-        let mut array_of_zeroes = vec![];
-        while array_of_zeroes.len() < MAX_SYMBOL_LENGTH - config.data.symbol.len() {
-            array_of_zeroes.push(0u8);
+        if let Some(gate_creator) = ctx.accounts.nft_vault.data.token_gate_creator {
+            assert_holds_verified_nft(
+                ctx.accounts.payer.key,
+                &gate_accounts[0],
+                &gate_accounts[1],
+                &gate_creator,
+            )?;
         }
-        let new_symbol =
-            config.data.symbol.clone() + std::str::from_utf8(&array_of_zeroes).unwrap();
-        config.data.symbol = new_symbol;
 
-        // - 1 because we are going to be a creator
-        if config.data.creators.len() > MAX_CREATOR_LIMIT - 1 {
-            return Err(ErrorCode::TooManyCreators.into());
+        if let Some(cosigner) = ctx.accounts.nft_vault.data.cosigner {
+            assert_cosigned(CosignParams {
+                program_id: ctx.program_id,
+                nft_vault_key: ctx.accounts.nft_vault.key(),
+                cosigner,
+                nonce_account: cosign_accounts[0].clone(),
+                payer: ctx.accounts.payer.clone(),
+                instruction_sysvar_account: cosign_accounts[1].clone(),
+                rent: ctx.accounts.rent.to_account_info(),
+                system_program: ctx.accounts.system_program.clone(),
+                unix_timestamp: clock.unix_timestamp,
+            })?;
         }
 
-        let mut new_data = Config::discriminator().try_to_vec().unwrap();
-        new_data.append(&mut config.try_to_vec().unwrap());
-        let mut data = config_info.data.borrow_mut();
-        // god forgive me couldnt think of better way to deal with this
-        for i in 0..new_data.len() {
-            data[i] = new_data[i];
+        if let Some((max_per_wallet, phase)) = wallet_mint_limit {
+            record_wallet_mints(WalletMintParams {
+                program_id: ctx.program_id,
+                nft_vault_key: ctx.accounts.nft_vault.key(),
+                max_per_wallet,
+                phase,
+                count: 1,
+                counter: counter[0].clone(),
+                minter: *ctx.accounts.payer.key,
+                payer: ctx.accounts.payer.clone(),
+                rent: ctx.accounts.rent.to_account_info(),
+                system_program: ctx.accounts.system_program.clone(),
+            })?;
         }
 
-        let vec_start =
-            CONFIG_ARRAY_START + 4 + (config.data.max_number_of_lines as usize) * CONFIG_LINE_SIZE;
-        let as_bytes = (config
-            .data
-            .max_number_of_lines
-            .checked_div(8)
-            .ok_or(ErrorCode::NumericalOverflowError)? as u32)
-            .to_le_bytes();
-        for i in 0..4 {
-            data[vec_start + i] = as_bytes[i]
+        let burn_token_account = assert_holds_verified_nft(
+            ctx.accounts.payer.key,
+            &ctx.accounts.burn_token_account,
+            &ctx.accounts.burn_metadata,
+            &creator,
+        )?;
+        if burn_token_account.mint != *ctx.accounts.burn_mint.key {
+            return Err(ErrorCode::MintMismatch.into());
         }
 
-        Ok(())
-    }
+        assert_owned_by(&ctx.accounts.burn_edition, &spl_token_metadata::id())?;
+        spl_token_metadata::utils::assert_edition_valid(
+            &spl_token_metadata::id(),
+            ctx.accounts.burn_mint.key,
+            &ctx.accounts.burn_edition,
+        )?;
 
-    pub fn add_config_lines(
-        ctx: Context<AddConfigLines>,
-        index: u32,
-        config_lines: Vec<ConfigLine>,
-    ) -> ProgramResult {
-        let config = &mut ctx.accounts.config;
-        let account = config.to_account_info();
-        let current_count = get_config_count(&account.data.borrow())?;
-        let mut data = account.data.borrow_mut();
+        msg!("Burning {}", ctx.accounts.burn_mint.key);
+        spl_token_burn(TokenBurnParams {
+            mint: ctx.accounts.burn_mint.clone(),
+            source: ctx.accounts.burn_token_account.clone(),
+            amount: 1,
+            authority: ctx.accounts.payer.clone(),
+            authority_signer_seeds: &[],
+            token_program: ctx.accounts.token_program.clone(),
+        })?;
 
-        let mut fixed_config_lines = vec![];
+        let config_info = ctx.accounts.config.to_account_info();
+        let nft_vault_info = ctx.accounts.nft_vault.to_account_info();
+        mint_item(MintItemParams {
+            program_id: ctx.program_id,
+            config: &ctx.accounts.config,
+            config_info,
+            config_pages,
+            print_accounts,
+            nft_vault: &mut ctx.accounts.nft_vault,
+            nft_vault_info,
+            metadata: ctx.accounts.metadata.clone(),
+            mint: ctx.accounts.mint.clone(),
+            mint_authority: ctx.accounts.mint_authority.clone(),
+            update_authority: ctx.accounts.update_authority.clone(),
+            master_edition: ctx.accounts.master_edition.clone(),
+            payer: ctx.accounts.payer.clone(),
+            token_metadata_program: ctx.accounts.token_metadata_program.clone(),
+            token_program: ctx.accounts.token_program.clone(),
+            system_program: ctx.accounts.system_program.clone(),
+            rent: ctx.accounts.rent.to_account_info(),
+            recent_slothashes: ctx.accounts.recent_slothashes.clone(),
+            instruction_sysvar,
+            edition_marker,
+            receipt,
+            price: 0,
+            payment_mint: None,
+            unix_timestamp: clock.unix_timestamp,
+        })?;
 
-        if index > config.data.max_number_of_lines - 1 {
-            return Err(ErrorCode::IndexGreaterThanLength.into());
-        }
+        Ok(())
+    }
 
-        for line in &config_lines {
-            let mut array_of_zeroes = vec![];
-            while array_of_zeroes.len() < MAX_NAME_LENGTH - line.name.len() {
-                array_of_zeroes.push(0u8);
-            }
-            let name = line.name.clone() + std::str::from_utf8(&array_of_zeroes).unwrap();
+    /// Mints one item from the team reserve to the authority without payment. Team mints
+    /// ignore the sale schedule and never take from the items left for the public.
+    /// Remaining accounts follow mint_nft: the receipt, instructions sysvar and config
+    /// pages when the vault uses them, then the print accounts.
+    pub fn team_mint<'info>(ctx: Context<'_, '_, '_, 'info, TeamMint<'info>>) -> ProgramResult {
+        let clock = &ctx.accounts.clock;
+        let nft_vault = &mut ctx.accounts.nft_vault;
 
-            let mut array_of_zeroes = vec![];
-            while array_of_zeroes.len() < MAX_URI_LENGTH - line.uri.len() {
-                array_of_zeroes.push(0u8);
-            }
-            let uri = line.uri.clone() + std::str::from_utf8(&array_of_zeroes).unwrap();
-            fixed_config_lines.push(ConfigLine { name, uri })
+        if nft_vault.team_minted >= nft_vault.data.team_reserve {
+            return Err(ErrorCode::TeamReserveExhausted.into());
+        }
+        if nft_vault.items_redeemed >= nft_vault.data.items_available {
+            return Err(ErrorCode::NftVaultEmpty.into());
         }
+        nft_vault.team_minted += 1;
+        msg!(
+            "Team minted {} of {}",
+            nft_vault.team_minted,
+            nft_vault.data.team_reserve
+        );
 
-        let as_vec = fixed_config_lines.try_to_vec()?;
-        let serialized: &[u8] = &as_vec.as_slice()[4..];
+        let mut remaining_accounts = ctx.remaining_accounts;
+        let receipt = take_receipt_account(&ctx.accounts.nft_vault, &mut remaining_accounts)?;
+        let instruction_sysvar = take_draw_account(
+            &ctx.accounts.nft_vault,
+            &ctx.accounts.config,
+            &mut remaining_accounts,
+        )?;
+        let config_pages = take_config_pages(
+            &ctx.accounts.nft_vault,
+            &ctx.accounts.config,
+            &mut remaining_accounts,
+        )?;
+        let (remaining_accounts, edition_marker) =
+            split_edition_marker(&ctx.accounts.nft_vault, remaining_accounts)?;
+        let (_, print_accounts) =
+            split_print_accounts(&ctx.accounts.nft_vault, remaining_accounts)?;
 
-        let position = CONFIG_ARRAY_START + 4 + (index as usize) * CONFIG_LINE_SIZE;
+        let config_info = ctx.accounts.config.to_account_info();
+        let nft_vault_info = ctx.accounts.nft_vault.to_account_info();
+        mint_item(MintItemParams {
+            program_id: ctx.program_id,
+            config: &ctx.accounts.config,
+            config_info,
+            config_pages,
+            print_accounts,
+            nft_vault: &mut ctx.accounts.nft_vault,
+            nft_vault_info,
+            metadata: ctx.accounts.metadata.clone(),
+            mint: ctx.accounts.mint.clone(),
+            mint_authority: ctx.accounts.mint_authority.clone(),
+            update_authority: ctx.accounts.update_authority.clone(),
+            master_edition: ctx.accounts.master_edition.clone(),
+            payer: ctx.accounts.payer.clone(),
+            token_metadata_program: ctx.accounts.token_metadata_program.clone(),
+            token_program: ctx.accounts.token_program.clone(),
+            system_program: ctx.accounts.system_program.clone(),
+            rent: ctx.accounts.rent.to_account_info(),
+            recent_slothashes: ctx.accounts.recent_slothashes.clone(),
+            instruction_sysvar,
+            edition_marker,
+            receipt,
+            price: 0,
+            payment_mint: None,
+            unix_timestamp: clock.unix_timestamp,
+        })?;
 
-        let array_slice: &mut [u8] =
-            &mut data[position..position + fixed_config_lines.len() * CONFIG_LINE_SIZE];
-        array_slice.copy_from_slice(serialized);
+        Ok(())
+    }
 
-        let bit_mask_vec_start = CONFIG_ARRAY_START
-            + 4
-            + (config.data.max_number_of_lines as usize) * CONFIG_LINE_SIZE
-            + 4;
+    pub fn initialize_raffle(
+        ctx: Context<InitializeRaffle>,
+        bump: u8,
+        entry_start: i64,
+        entry_end: i64,
+        winner_count: u32,
+        max_tickets: u32,
+    ) -> ProgramResult {
+        let nft_vault = &mut ctx.accounts.nft_vault;
+        let raffle = &mut ctx.accounts.raffle;
 
-        let mut new_count = current_count;
-        for i in 0..fixed_config_lines.len() {
-            let position = (index as usize)
-                .checked_add(i)
-                .ok_or(ErrorCode::NumericalOverflowError)?;
-            let my_position_in_vec = bit_mask_vec_start
-                + position
-                    .checked_div(8)
-                    .ok_or(ErrorCode::NumericalOverflowError)?;
-            let position_from_right = 7 - position
-                .checked_rem(8)
-                .ok_or(ErrorCode::NumericalOverflowError)?;
-            let mask = u8::pow(2, position_from_right as u32);
+        if entry_start >= entry_end || winner_count == 0 || max_tickets == 0 {
+            return Err(ErrorCode::InvalidRaffle.into());
+        }
+        // Every winner must be able to mint from the public supply
+        assert_public_supply(nft_vault, winner_count as u64)
+            .map_err(|_| ErrorCode::InvalidRaffle)?;
+        // Tickets are bought at the vault price in the vault's own currency
+        if !nft_vault.payment_options.is_empty() {
+            return Err(ErrorCode::RafflePaymentOptionConflict.into());
+        }
+        if nft_vault.data.dutch_auction.is_some() || !nft_vault.data.phases.is_empty() {
+            return Err(ErrorCode::RafflePricingConflict.into());
+        }
 
-            let old_value_in_vec = data[my_position_in_vec];
-            data[my_position_in_vec] = data[my_position_in_vec] | mask;
-            msg!(
-                "My position in vec is {} my mask is going to be {}, the old value is {}",
-                position,
-                mask,
-                old_value_in_vec
-            );
-            msg!(
-                "My new value is {} and my position from right is {}",
-                data[my_position_in_vec],
-                position_from_right
-            );
-            if old_value_in_vec != data[my_position_in_vec] {
-                msg!("Increasing count");
-                new_count = new_count
-                    .checked_add(1)
-                    .ok_or(ErrorCode::NumericalOverflowError)?;
+        if let Some(mint) = nft_vault.token_mint {
+            let escrow_info = match ctx.remaining_accounts.first() {
+                Some(val) => val,
+                None => return Err(ErrorCode::RaffleAccountsMissing.into()),
+            };
+            let escrow: Account = assert_initialized(escrow_info)?;
+            assert_owned_by(escrow_info, &spl_token::id())?;
+            if escrow.mint != mint {
+                return Err(ErrorCode::MintMismatch.into());
+            }
+            if escrow.owner != raffle.key() {
+                return Err(ErrorCode::IncorrectOwner.into());
             }
+            raffle.escrow = Some(*escrow_info.key);
         }
 
-        data[CONFIG_ARRAY_START..CONFIG_ARRAY_START + 4]
-            .copy_from_slice(&(new_count as u32).to_le_bytes());
+        raffle.nft_vault = nft_vault.key();
+        raffle.entry_start = entry_start;
+        raffle.entry_end = entry_end;
+        raffle.winner_count = winner_count;
+        raffle.max_tickets = max_tickets;
+        raffle.bump = bump;
+        raffle.winners = vec![0u8; max_tickets as usize / 8 + 1];
+        nft_vault.raffle = Some(raffle.key());
+        assert_nft_vault_fits(nft_vault, nft_vault.to_account_info().data_len())?;
 
         Ok(())
     }
 
-    pub fn initialize_nft_vault(
-        ctx: Context<InitializeNftVault>,
-        bump: u8,
-        data: NftVaultData,
+    /// Buys a raffle ticket at the vault price, one per wallet. The payment stays in escrow,
+    /// in the ticket account for sol or in the raffle escrow token account otherwise, until
+    /// the ticket is redeemed through mint_nft or refunded. Remaining accounts hold the
+    /// token account, transfer authority and raffle escrow for spl vaults, then the token
+    /// gate accounts when the vault has a token gate.
+    pub fn buy_raffle_ticket<'info>(
+        ctx: Context<'_, '_, '_, 'info, BuyRaffleTicket<'info>>,
     ) -> ProgramResult {
-        let nft_vault = &mut ctx.accounts.nft_vault;
+        let raffle = &mut ctx.accounts.raffle;
+        let nft_vault = &ctx.accounts.nft_vault;
+        let ticket_info = &ctx.accounts.ticket;
+        let payer = &ctx.accounts.payer;
+        let clock = &ctx.accounts.clock;
 
-        if data.uuid.len() != 6 {
-            return Err(ErrorCode::UuidMustBeExactly6Length.into());
+        if clock.unix_timestamp < raffle.entry_start || clock.unix_timestamp >= raffle.entry_end {
+            return Err(ErrorCode::RaffleEntryClosed.into());
+        }
+        if raffle.ticket_count >= raffle.max_tickets {
+            return Err(ErrorCode::RaffleFull.into());
         }
-        nft_vault.data = data;
-        nft_vault.wallet = *ctx.accounts.wallet.key;
-        nft_vault.authority = *ctx.accounts.authority.key;
-        nft_vault.config = ctx.accounts.config.key();
-        nft_vault.bump = bump;
-        if ctx.remaining_accounts.len() > 0 {
-            let token_mint_info = &ctx.remaining_accounts[0];
-            let _token_mint: Mint = assert_initialized(&token_mint_info)?;
-            let token_account: Account = assert_initialized(&ctx.accounts.wallet)?;
-
-            assert_owned_by(&token_mint_info, &spl_token::id())?;
-            assert_owned_by(&ctx.accounts.wallet, &spl_token::id())?;
-
-            if token_account.mint != *token_mint_info.key {
-                return Err(ErrorCode::MintMismatch.into());
-            }
 
-            nft_vault.token_mint = Some(*token_mint_info.key);
+        let mut remaining_accounts = ctx.remaining_accounts;
+        let payment_accounts = match nft_vault.token_mint {
+            Some(_) => take_accounts(&mut remaining_accounts, 3, ErrorCode::RaffleAccountsMissing)?,
+            None => &[],
+        };
+        if let Some(creator) = nft_vault.data.token_gate_creator {
+            let gate_accounts = take_accounts(
+                &mut remaining_accounts,
+                2,
+                ErrorCode::TokenGateAccountsMissing,
+            )?;
+            assert_holds_verified_nft(payer.key, &gate_accounts[0], &gate_accounts[1], &creator)?;
         }
 
-        if get_config_count(&ctx.accounts.config.to_account_info().data.borrow())?
-            < nft_vault.data.items_available as usize
-        {
-            return Err(ErrorCode::ConfigLineMismatch.into());
+        let raffle_key = raffle.key();
+        let (ticket_key, ticket_bump) =
+            find_raffle_ticket_address(ctx.program_id, &raffle_key, payer.key);
+        if ticket_key != *ticket_info.key {
+            return Err(ErrorCode::DerivedKeyInvalid.into());
+        }
+        if !ticket_info.data_is_empty() {
+            return Err(ErrorCode::RaffleTicketAlreadyBought.into());
         }
+        create_or_allocate_account_raw(
+            *ctx.program_id,
+            ticket_info,
+            &ctx.accounts.rent.to_account_info(),
+            &ctx.accounts.system_program,
+            payer,
+            RAFFLE_TICKET_SIZE,
+            &[
+                PREFIX.as_bytes(),
+                raffle_key.as_ref(),
+                TICKET.as_bytes(),
+                payer.key.as_ref(),
+                &[ticket_bump],
+            ],
+        )?;
 
-        let _config_line = match get_config_line(&ctx.accounts.config.to_account_info(), 0) {
-            Ok(val) => val,
-            Err(_) => return Err(ErrorCode::ConfigMustHaveAtleastOneEntry.into()),
-        };
+        let price = nft_vault.data.price;
+        if let Some(mint) = nft_vault.token_mint {
+            let token_account_info = &payment_accounts[0];
+            let transfer_authority_info = &payment_accounts[1];
+            let escrow_info = &payment_accounts[2];
+            let token_account: Account = assert_initialized(token_account_info)?;
+            assert_owned_by(token_account_info, &spl_token::id())?;
+
+            if token_account.mint != mint {
+                return Err(ErrorCode::MintMismatch.into());
+            }
+            if Some(*escrow_info.key) != raffle.escrow {
+                return Err(ErrorCode::RaffleMismatch.into());
+            }
+            if token_account.amount < price {
+                return Err(ErrorCode::NotEnoughTokens.into());
+            }
+
+            spl_token_transfer(TokenTransferParams {
+                source: token_account_info.clone(),
+                destination: escrow_info.clone(),
+                authority: transfer_authority_info.clone(),
+                authority_signer_seeds: &[],
+                token_program: ctx.accounts.token_program.clone(),
+                amount: price,
+            })?;
+        } else {
+            if payer.lamports() < price {
+                return Err(ErrorCode::NotEnoughSOL.into());
+            }
+
+            invoke(
+                &system_instruction::transfer(payer.key, ticket_info.key, price),
+                &[
+                    payer.clone(),
+                    ticket_info.clone(),
+                    ctx.accounts.system_program.clone(),
+                ],
+            )?;
+        }
+
+        RaffleTicket {
+            raffle: raffle_key,
+            buyer: *payer.key,
+            index: raffle.ticket_count,
+            price,
+            bump: ticket_bump,
+        }
+        .try_serialize(&mut &mut ticket_info.data.borrow_mut()[..])?;
+        msg!("Sold raffle ticket {}", raffle.ticket_count);
+
+        raffle.ticket_count = raffle
+            .ticket_count
+            .checked_add(1)
+            .ok_or(ErrorCode::NumericalOverflowError)?;
+
+        Ok(())
+    }
+
+    /// Draws up to `count` more winners once entry has closed. The first call commits to
+    /// the hash of a slot RAFFLE_DRAW_DELAY slots ahead, and once that slot has passed its
+    /// hash seeds every winner, so who calls this and with which `count` cannot change the
+    /// outcome. A committed slot that left the slot hashes sysvar unused is replaced.
+    pub fn draw_raffle(ctx: Context<DrawRaffle>, count: u32) -> ProgramResult {
+        let raffle = &mut ctx.accounts.raffle;
+        let clock = &ctx.accounts.clock;
+
+        if clock.unix_timestamp < raffle.entry_end {
+            return Err(ErrorCode::RaffleEntryNotEnded.into());
+        }
+
+        let target = get_raffle_winner_target(raffle);
+        if raffle.winners_drawn >= target {
+            return Err(ErrorCode::RaffleAlreadyDrawn.into());
+        }
+
+        let seed = match raffle.draw_seed {
+            Some(val) => val,
+            None => {
+                if raffle.draw_slot != 0 && clock.slot <= raffle.draw_slot {
+                    return Err(ErrorCode::RaffleDrawSlotNotReached.into());
+                }
+                let slot_hash = match raffle.draw_slot {
+                    0 => None,
+                    slot => find_slot_hash(&ctx.accounts.recent_slothashes.data.borrow(), slot),
+                };
+                match slot_hash {
+                    Some(val) => {
+                        raffle.draw_seed = Some(val);
+                        val
+                    }
+                    None => {
+                        raffle.draw_slot = clock
+                            .slot
+                            .checked_add(RAFFLE_DRAW_DELAY)
+                            .ok_or(ErrorCode::NumericalOverflowError)?;
+                        msg!("Raffle draws from the hash of slot {}", raffle.draw_slot);
+                        return Ok(());
+                    }
+                }
+            }
+        };
+
+        let raffle_key = raffle.key();
+        let mut drawn = 0;
+        while drawn < count && raffle.winners_drawn < target {
+            let index = get_raffle_draw(raffle, &seed, &raffle_key);
+            raffle.winners[index as usize / 8] |= 1u8 << (7 - index % 8);
+            msg!("Raffle ticket {} won", index);
+
+            raffle.winners_drawn += 1;
+            drawn += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the escrowed price and the ticket rent to the buyer. Losing tickets can be
+    /// refunded once every winner is drawn, winning ones only while the vault is paused,
+    /// past its end date or out of public supply, when they could not mint.
+    pub fn refund_raffle_ticket<'info>(
+        ctx: Context<'_, '_, '_, 'info, RefundRaffleTicket<'info>>,
+    ) -> ProgramResult {
+        let raffle = &mut ctx.accounts.raffle;
+        let nft_vault = &ctx.accounts.nft_vault;
+        let ticket_info = &ctx.accounts.ticket;
+        let buyer = &ctx.accounts.buyer;
+
+        if raffle.winners_drawn < get_raffle_winner_target(raffle) {
+            return Err(ErrorCode::RaffleNotDrawn.into());
+        }
+
+        assert_owned_by(ticket_info, ctx.program_id)?;
+        let ticket = RaffleTicket::try_deserialize(&mut &ticket_info.data.borrow()[..])?;
+        if ticket.raffle != raffle.key() || ticket.buyer != *buyer.key {
+            return Err(ErrorCode::RaffleTicketMismatch.into());
+        }
+        if is_raffle_winner(raffle, ticket.index) {
+            let unix_timestamp = Clock::get()?.unix_timestamp;
+            let sale_ended = match nft_vault.end_date {
+                Some(end_date) => unix_timestamp >= end_date,
+                None => false,
+            };
+            if !nft_vault.paused && !sale_ended && assert_public_supply(nft_vault, 1).is_ok() {
+                return Err(ErrorCode::RaffleWinnerCannotRefund.into());
+            }
+            raffle.winners_settled = raffle
+                .winners_settled
+                .checked_add(1)
+                .ok_or(ErrorCode::NumericalOverflowError)?;
+        }
+        raffle.tickets_settled = raffle
+            .tickets_settled
+            .checked_add(1)
+            .ok_or(ErrorCode::NumericalOverflowError)?;
+
+        if let Some(escrow) = raffle.escrow {
+            let (escrow_info, buyer_token_account_info) = match (
+                ctx.remaining_accounts.first(),
+                ctx.remaining_accounts.get(1),
+            ) {
+                (Some(a), Some(b)) => (a, b),
+                _ => return Err(ErrorCode::RaffleAccountsMissing.into()),
+            };
+            if *escrow_info.key != escrow {
+                return Err(ErrorCode::RaffleMismatch.into());
+            }
+            let buyer_token_account: Account = assert_initialized(buyer_token_account_info)?;
+            if buyer_token_account.owner != ticket.buyer {
+                return Err(ErrorCode::RaffleTicketMismatch.into());
+            }
+
+            let nft_vault_key = raffle.nft_vault;
+            spl_token_transfer(TokenTransferParams {
+                source: escrow_info.clone(),
+                destination: buyer_token_account_info.clone(),
+                authority: raffle.to_account_info(),
+                authority_signer_seeds: &[
+                    PREFIX.as_bytes(),
+                    nft_vault_key.as_ref(),
+                    RAFFLE.as_bytes(),
+                    &[raffle.bump],
+                ],
+                token_program: ctx.accounts.token_program.clone(),
+                amount: ticket.price,
+            })?;
+        }
+
+        msg!("Refunding raffle ticket {}", ticket.index);
+        close_program_account(ticket_info, buyer)?;
+
+        Ok(())
+    }
+
+    /// Closes a raffle once every ticket is redeemed or refunded, after which the vault
+    /// sells to the public again and can be closed. Spl raffles pass their escrow as the
+    /// only remaining account: tokens left in it go to the vault wallet and its rent, like
+    /// the raffle's, to the recipient.
+    pub fn close_raffle<'info>(
+        ctx: Context<'_, '_, '_, 'info, CloseRaffle<'info>>,
+    ) -> ProgramResult {
+        let raffle = &ctx.accounts.raffle;
+        assert_raffle_settled(raffle, Clock::get()?.unix_timestamp)?;
+
+        if let Some(escrow) = raffle.escrow {
+            let escrow_info = match ctx.remaining_accounts.first() {
+                Some(val) => val,
+                None => return Err(ErrorCode::RaffleAccountsMissing.into()),
+            };
+            if *escrow_info.key != escrow {
+                return Err(ErrorCode::RaffleMismatch.into());
+            }
+            let escrow_account: Account = assert_initialized(escrow_info)?;
+
+            let nft_vault_key = raffle.nft_vault;
+            let raffle_seeds = [
+                PREFIX.as_bytes(),
+                nft_vault_key.as_ref(),
+                RAFFLE.as_bytes(),
+                &[raffle.bump],
+            ];
+            if escrow_account.amount > 0 {
+                spl_token_transfer(TokenTransferParams {
+                    source: escrow_info.clone(),
+                    destination: ctx.accounts.wallet.clone(),
+                    authority: raffle.to_account_info(),
+                    authority_signer_seeds: &raffle_seeds,
+                    token_program: ctx.accounts.token_program.clone(),
+                    amount: escrow_account.amount,
+                })?;
+            }
+            spl_token_close_account(TokenCloseParams {
+                account: escrow_info.clone(),
+                destination: ctx.accounts.recipient.clone(),
+                authority: raffle.to_account_info(),
+                authority_signer_seeds: &raffle_seeds,
+                token_program: ctx.accounts.token_program.clone(),
+            })?;
+        }
+
+        ctx.accounts.nft_vault.raffle = None;
+        msg!(
+            "Closing raffle, sending {} lamports to recipient",
+            raffle.to_account_info().lamports()
+        );
+
+        Ok(())
+    }
+
+    pub fn set_payment_option(ctx: Context<SetPaymentOption>, price: u64) -> ProgramResult {
+        let nft_vault = &mut ctx.accounts.nft_vault;
+        let mint_info = &ctx.accounts.mint;
+        let treasury_info = &ctx.accounts.treasury;
+
+        let _mint: Mint = assert_initialized(mint_info)?;
+        let treasury: Account = assert_initialized(treasury_info)?;
+
+        assert_owned_by(mint_info, &spl_token::id())?;
+        assert_owned_by(treasury_info, &spl_token::id())?;
+
+        if treasury.mint != *mint_info.key {
+            return Err(ErrorCode::MintMismatch.into());
+        }
+
+        // A fixed option price would undercut a declining or phase dependent price
+        if nft_vault.data.dutch_auction.is_some() || !nft_vault.data.phases.is_empty() {
+            return Err(ErrorCode::PaymentOptionPricingConflict.into());
+        }
+        if nft_vault.raffle.is_some() {
+            return Err(ErrorCode::RafflePaymentOptionConflict.into());
+        }
+
+        match nft_vault
+            .payment_options
+            .iter_mut()
+            .find(|o| o.mint == *mint_info.key)
+        {
+            Some(existing) => {
+                existing.price = price;
+                existing.treasury = *treasury_info.key;
+            }
+            None => {
+                if nft_vault.payment_options.len() >= MAX_PAYMENT_OPTIONS {
+                    return Err(ErrorCode::TooManyPaymentOptions.into());
+                }
+                nft_vault.payment_options.push(PaymentOption {
+                    mint: *mint_info.key,
+                    price,
+                    treasury: *treasury_info.key,
+                });
+            }
+        }
+        msg!("Payment option for mint {} set to {}", mint_info.key, price);
+        assert_nft_vault_fits(nft_vault, nft_vault.to_account_info().data_len())?;
+
+        Ok(())
+    }
+
+    pub fn remove_payment_option(ctx: Context<UpdateNftVault>, mint: Pubkey) -> ProgramResult {
+        let nft_vault = &mut ctx.accounts.nft_vault;
+        let len_before = nft_vault.payment_options.len();
+        nft_vault.payment_options.retain(|o| o.mint != mint);
+        if nft_vault.payment_options.len() == len_before {
+            return Err(ErrorCode::PaymentOptionNotFound.into());
+        }
+        msg!("Payment option for mint {} removed", mint);
+
+        Ok(())
+    }
+
+    pub fn update_nft_vault(
+        ctx: Context<UpdateNftVault>,
+        price: Option<u64>,
+        go_live_date: Option<i64>,
+        allowlist_root: Option<[u8; 32]>,
+        end_date: Option<i64>,
+        paused: Option<bool>,
+        phases: Option<Vec<SalePhase>>,
+    ) -> ProgramResult {
+        let nft_vault = &mut ctx.accounts.nft_vault;
+        // Read through the sysvar syscall so UpdateNftVault keeps its original accounts,
+        // which remove_payment_option and propose_authority share
+        let now = Clock::get()?.unix_timestamp;
+
+        if let Some(new_phases) = phases {
+            if !new_phases.is_empty() && !nft_vault.payment_options.is_empty() {
+                return Err(ErrorCode::PaymentOptionPricingConflict.into());
+            }
+            if !new_phases.is_empty() && nft_vault.raffle.is_some() {
+                return Err(ErrorCode::RafflePricingConflict.into());
+            }
+            // Phases that have started are kept as they are, later ones can be rewritten
+            let started = nft_vault
+                .data
+                .phases
+                .iter()
+                .take_while(|phase| phase.start_time <= now)
+                .count();
+            if new_phases.len() < started
+                || new_phases[..started] != nft_vault.data.phases[..started]
+                || new_phases[started..]
+                    .iter()
+                    .any(|phase| phase.start_time <= now)
+            {
+                return Err(ErrorCode::SalePhaseStarted.into());
+            }
+            nft_vault.data.phases = new_phases;
+            assert_valid_phases(&nft_vault.data)?;
+            msg!("Sale phases changed");
+        }
+
+        if let Some(p) = price {
+            nft_vault.data.price = p;
+        }
+
+        if let Some(go_l) = go_live_date {
+            msg!("Go live date changed to {}", go_l);
+            nft_vault.data.go_live_date = Some(go_l)
+        }
+
+        if let Some(root) = allowlist_root {
+            msg!("Allowlist root changed");
+            nft_vault.allowlist_root = Some(root);
+        }
+
+        if let Some(end) = end_date {
+            msg!("End date changed to {}", end);
+            nft_vault.end_date = Some(end);
+        }
+
+        if let Some(p) = paused {
+            msg!("Paused changed to {}", p);
+            nft_vault.paused = p;
+        }
+        assert_nft_vault_fits(nft_vault, nft_vault.to_account_info().data_len())?;
+
+        Ok(())
+    }
+
+    /// Changes `items_available`, checked like initialize_nft_vault checks it, and never
+    /// below what the public and raffle winners are owed. Vaults with a raffle pass it as
+    /// the first remaining account, print vaults pass the master edition as the last one.
+    pub fn update_items_available<'info>(
+        ctx: Context<'_, '_, '_, 'info, UpdateItemsAvailable<'info>>,
+        items_available: u64,
+    ) -> ProgramResult {
+        let nft_vault = &mut ctx.accounts.nft_vault;
+
+        if items_available < nft_vault.items_redeemed {
+            return Err(ErrorCode::ItemsAvailableBelowRedeemed.into());
+        }
+        if nft_vault.data.team_reserve > items_available {
+            return Err(ErrorCode::TeamReserveTooLarge.into());
+        }
+
+        // Raffle winners still to redeem are owed items from the public supply
+        let mut remaining_accounts = ctx.remaining_accounts;
+        let unredeemed_winners = match nft_vault.raffle {
+            Some(raffle_key) => {
+                let raffle_info =
+                    &take_accounts(&mut remaining_accounts, 1, ErrorCode::RaffleAccountsMissing)?
+                        [0];
+                if *raffle_info.key != raffle_key {
+                    return Err(ErrorCode::RaffleMismatch.into());
+                }
+                assert_owned_by(raffle_info, ctx.program_id)?;
+                let raffle = Raffle::try_deserialize(&mut &raffle_info.data.borrow()[..])?;
+                get_unredeemed_winners(&raffle)?
+            }
+            None => 0,
+        };
+        assert_supply_covers(nft_vault, items_available, unredeemed_winners)?;
+
+        let config = &ctx.accounts.config;
+        if nft_vault.data.require_frozen_config
+            && config.frozen
+            && items_available > config.freeze_progress as u64
+        {
+            return Err(ErrorCode::ItemsAvailableExceedFrozenLines.into());
+        }
+
+        if let Some(hidden_settings) = &nft_vault.data.hidden_settings {
+            // The reveal hash covers exactly `items_available` lines
+            if nft_vault.reveal_progress > 0 || nft_vault.revealed {
+                return Err(ErrorCode::RevealAlreadyStarted.into());
+            }
+            assert_hidden_settings_fit(hidden_settings, items_available)?;
+        } else if let Some(master_mint) = nft_vault.data.print_master_mint {
+            let source_edition = match remaining_accounts.last() {
+                Some(val) => val,
+                None => return Err(ErrorCode::PrintAccountsMissing.into()),
+            };
+            // Editions already minted are part of the master edition's supply
+            assert_print_supply(
+                &master_mint,
+                source_edition,
+                items_available - nft_vault.items_redeemed,
+            )?;
+        } else if get_total_config_count(&ctx.accounts.config)? < items_available as usize {
+            return Err(ErrorCode::ConfigLineMismatch.into());
+        }
+
+        msg!(
+            "Items available changed from {} to {}",
+            nft_vault.data.items_available,
+            items_available
+        );
+        nft_vault.data.items_available = items_available;
+
+        Ok(())
+    }
+
+    /// Points payments at a new wallet. Like initialize_nft_vault, a token mint as the first
+    /// remaining account makes the vault charge in that token, which the wallet must hold.
+    /// Without one the vault charges sol.
+    pub fn update_wallet<'info>(
+        ctx: Context<'_, '_, '_, 'info, UpdateWallet<'info>>,
+    ) -> ProgramResult {
+        let nft_vault = &mut ctx.accounts.nft_vault;
+
+        // Raffle tickets are escrowed and redeemed in the currency they were bought in
+        let token_mint = get_wallet_token_mint(&ctx.accounts.wallet, ctx.remaining_accounts)?;
+        if nft_vault.raffle.is_some() && token_mint != nft_vault.token_mint {
+            return Err(ErrorCode::RaffleCurrencyLocked.into());
+        }
+        if nft_vault.data.bot_tax.is_some() && token_mint.is_some() {
+            return Err(ErrorCode::BotTaxRequiresSolWallet.into());
+        }
+        nft_vault.token_mint = token_mint;
+        nft_vault.wallet = *ctx.accounts.wallet.key;
+        msg!("Wallet changed to {}", nft_vault.wallet);
+        assert_nft_vault_fits(nft_vault, nft_vault.to_account_info().data_len())?;
+
+        Ok(())
+    }
+
+    /// First step of an authority transfer, `None` withdraws a pending proposal. The
+    /// current authority keeps control until the new one calls accept_authority.
+    pub fn propose_authority(
+        ctx: Context<UpdateNftVault>,
+        new_authority: Option<Pubkey>,
+    ) -> ProgramResult {
+        let nft_vault = &mut ctx.accounts.nft_vault;
+        nft_vault.pending_authority = new_authority;
+        match new_authority {
+            Some(val) => msg!("Authority {} proposed", val),
+            None => msg!("Authority proposal withdrawn"),
+        }
+        assert_nft_vault_fits(nft_vault, nft_vault.to_account_info().data_len())?;
+
+        Ok(())
+    }
+
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> ProgramResult {
+        let nft_vault = &mut ctx.accounts.nft_vault;
+        if nft_vault.pending_authority != Some(*ctx.accounts.new_authority.key) {
+            return Err(ErrorCode::NotPendingAuthority.into());
+        }
+
+        nft_vault.authority = *ctx.accounts.new_authority.key;
+        nft_vault.pending_authority = None;
+        msg!("Authority changed to {}", nft_vault.authority);
+
+        Ok(())
+    }
+
+    /// Creates a config, storing lines as uri suffixes only with `compact` settings. The
+    /// settings go into the config extension, so configs without them pay no rent for it.
+    pub fn initialize_config(
+        ctx: Context<InitializeConfig>,
+        data: ConfigData,
+        compact: Option<CompactConfig>,
+    ) -> ProgramResult {
+        let config_info = &mut ctx.accounts.config;
+        if data.uuid.len() != 6 {
+            return Err(ErrorCode::UuidMustBeExactly6Length.into());
+        }
+
+        let extension = ConfigExtension {
+            compact,
+            content_hash: [0u8; 32],
+        };
+        let extension_bytes = extension.try_to_vec()?;
+        let mut config = Config {
+            data,
+            authority: *ctx.accounts.authority.key,
+            vault_count: 0,
+            page_count: 0,
+            page_capacity: 0,
+            page_line_count: 0,
+            freeze_progress: 0,
+            frozen: false,
+            version: CONFIG_VERSION,
+            extension_len: extension_bytes.len() as u16,
+        };
+
+        let mut array_of_zeroes = vec![];
+        while array_of_zeroes.len() < MAX_SYMBOL_LENGTH - config.data.symbol.len() {
+            array_of_zeroes.push(0u8);
+        }
+        let new_symbol =
+            config.data.symbol.clone() + std::str::from_utf8(&array_of_zeroes).unwrap();
+        config.data.symbol = new_symbol;
+
+        // - 1 because we are going to be a creator
+        if config.data.creators.len() > MAX_CREATOR_LIMIT - 1 {
+            return Err(ErrorCode::TooManyCreators.into());
+        }
+
+        if let Some(compact) = &extension.compact {
+            // Leave room for the longest line number in the name
+            if compact.suffix_length == 0
+                || compact.uri_prefix.len() + compact.suffix_length as usize > MAX_URI_LENGTH
+                || !compact.name_template.contains("{}")
+                || compact.name_template.len() - 2 + u32::MAX.to_string().len() > MAX_NAME_LENGTH
+            {
+                return Err(ErrorCode::InvalidCompactConfig.into());
+            }
+        }
+
+        let mut new_data = Config::discriminator().try_to_vec().unwrap();
+        new_data.append(&mut config.try_to_vec().unwrap());
+        let mut data = config_info.data.borrow_mut();
+        // god forgive me couldnt think of better way to deal with this
+        for i in 0..new_data.len() {
+            data[i] = new_data[i];
+        }
+        let array_start = get_config_array_start(&config);
+        data[CONFIG_ARRAY_START..array_start].copy_from_slice(&extension_bytes);
+
+        let vec_start = array_start
+            + 4
+            + (config.data.max_number_of_lines as usize) * get_config_line_size(&extension.compact);
+        let as_bytes =
+            (get_config_bitmask_len(config.data.max_number_of_lines) as u32).to_le_bytes();
+        for i in 0..4 {
+            data[vec_start + i] = as_bytes[i]
+        }
 
         Ok(())
     }
+
+    pub fn add_config_lines<'info>(
+        ctx: Context<'_, '_, '_, 'info, AddConfigLines<'info>>,
+        index: u32,
+        config_lines: Vec<ConfigLine>,
+    ) -> ProgramResult {
+        let config = &mut ctx.accounts.config;
+        if config.frozen || config.freeze_progress > 0 {
+            return Err(ErrorCode::ConfigFrozen.into());
+        }
+
+        let config_info = config.to_account_info();
+        let segment = get_config_segment(
+            &config_info,
+            config,
+            ctx.remaining_accounts,
+            ctx.program_id,
+            index as usize,
+        )?;
+        let account = segment.account.clone();
+        let current_count = get_config_count_at(&account.data.borrow(), segment.array_start)?;
+        let mut data = account.data.borrow_mut();
+
+        let mut fixed_config_lines = vec![];
+
+        // Lines are written into a single account, the config itself or one of its pages
+        let index = (index as usize - segment.start_index) as u32;
+        if index as usize + config_lines.len() > segment.max_number_of_lines as usize {
+            return Err(ErrorCode::IndexGreaterThanLength.into());
+        }
+
+        let as_vec = match &segment.compact {
+            // Compact lines only keep the uri suffix, the rest comes from the config
+            Some(compact) => {
+                let mut suffixes = vec![];
+                for line in &config_lines {
+                    if line.uri.len() > compact.suffix_length as usize {
+                        return Err(ErrorCode::ConfigLineTooLong.into());
+                    }
+                    let mut array_of_zeroes = vec![];
+                    while array_of_zeroes.len() < compact.suffix_length as usize - line.uri.len() {
+                        array_of_zeroes.push(0u8);
+                    }
+                    suffixes
+                        .push(line.uri.clone() + std::str::from_utf8(&array_of_zeroes).unwrap());
+                }
+                suffixes.try_to_vec()?
+            }
+            None => {
+                for line in &config_lines {
+                    let mut array_of_zeroes = vec![];
+                    while array_of_zeroes.len() < MAX_NAME_LENGTH - line.name.len() {
+                        array_of_zeroes.push(0u8);
+                    }
+                    let name = line.name.clone() + std::str::from_utf8(&array_of_zeroes).unwrap();
+
+                    let mut array_of_zeroes = vec![];
+                    while array_of_zeroes.len() < MAX_URI_LENGTH - line.uri.len() {
+                        array_of_zeroes.push(0u8);
+                    }
+                    let uri = line.uri.clone() + std::str::from_utf8(&array_of_zeroes).unwrap();
+                    fixed_config_lines.push(ConfigLine { name, uri })
+                }
+                fixed_config_lines.try_to_vec()?
+            }
+        };
+        let serialized: &[u8] = &as_vec.as_slice()[4..];
+
+        let position = segment.array_start + 4 + (index as usize) * segment.line_size;
+
+        let array_slice: &mut [u8] =
+            &mut data[position..position + config_lines.len() * segment.line_size];
+        array_slice.copy_from_slice(serialized);
+
+        let bit_mask_vec_start = segment.array_start
+            + 4
+            + (segment.max_number_of_lines as usize) * segment.line_size
+            + 4;
+
+        let mut new_count = current_count;
+        for i in 0..config_lines.len() {
+            let position = (index as usize)
+                .checked_add(i)
+                .ok_or(ErrorCode::NumericalOverflowError)?;
+            let my_position_in_vec = bit_mask_vec_start
+                + position
+                    .checked_div(8)
+                    .ok_or(ErrorCode::NumericalOverflowError)?;
+            let position_from_right = 7 - position
+                .checked_rem(8)
+                .ok_or(ErrorCode::NumericalOverflowError)?;
+            let mask = u8::pow(2, position_from_right as u32);
+
+            let old_value_in_vec = data[my_position_in_vec];
+            data[my_position_in_vec] = data[my_position_in_vec] | mask;
+            msg!(
+                "My position in vec is {} my mask is going to be {}, the old value is {}",
+                position,
+                mask,
+                old_value_in_vec
+            );
+            msg!(
+                "My new value is {} and my position from right is {}",
+                data[my_position_in_vec],
+                position_from_right
+            );
+            if old_value_in_vec != data[my_position_in_vec] {
+                msg!("Increasing count");
+                new_count = new_count
+                    .checked_add(1)
+                    .ok_or(ErrorCode::NumericalOverflowError)?;
+            }
+        }
+
+        data[segment.array_start..segment.array_start + 4]
+            .copy_from_slice(&(new_count as u32).to_le_bytes());
+
+        if segment.array_start == CONFIG_PAGE_ARRAY_START {
+            config.page_line_count = config
+                .page_line_count
+                .checked_add((new_count - current_count) as u32)
+                .ok_or(ErrorCode::NumericalOverflowError)?;
+        }
+
+        Ok(())
+    }
+
+    /// Links a preallocated page to the config, extending it by `max_number_of_lines`
+    /// lines that follow every line of the config and of its earlier pages.
+    pub fn initialize_config_page(
+        ctx: Context<InitializeConfigPage>,
+        max_number_of_lines: u32,
+    ) -> ProgramResult {
+        let config = &mut ctx.accounts.config;
+        let page_info = &ctx.accounts.config_page;
+
+        if config.frozen || config.freeze_progress > 0 {
+            return Err(ErrorCode::ConfigFrozen.into());
+        }
+        if max_number_of_lines == 0 {
+            return Err(ErrorCode::IndexGreaterThanLength.into());
+        }
+        if page_info.data.borrow()[..8] != [0u8; 8] {
+            return Err(ErrorCode::ConfigPageAlreadyInitialized.into());
+        }
+
+        let start_index = config
+            .data
+            .max_number_of_lines
+            .checked_add(config.page_capacity)
+            .ok_or(ErrorCode::NumericalOverflowError)?;
+        let page = ConfigPage {
+            config: config.key(),
+            page_index: config.page_count,
+            start_index,
+            max_number_of_lines,
+        };
+        let mut data = page_info.data.borrow_mut();
+        page.try_serialize(&mut &mut data[..])?;
+
+        let compact = get_config_extension(&config.to_account_info(), config)?.compact;
+        let vec_start = CONFIG_PAGE_ARRAY_START
+            + 4
+            + (max_number_of_lines as usize) * get_config_line_size(&compact);
+        data[vec_start..vec_start + 4]
+            .copy_from_slice(&(get_config_bitmask_len(max_number_of_lines) as u32).to_le_bytes());
+
+        msg!(
+            "Config page {} holds lines {} to {}",
+            page.page_index,
+            start_index,
+            start_index as u64 + max_number_of_lines as u64 - 1
+        );
+        config.page_count = config
+            .page_count
+            .checked_add(1)
+            .ok_or(ErrorCode::NumericalOverflowError)?;
+        config.page_capacity = config
+            .page_capacity
+            .checked_add(max_number_of_lines)
+            .ok_or(ErrorCode::NumericalOverflowError)?;
+
+        Ok(())
+    }
+
+    /// Hashes the next `batch_size` of the first `items_available` lines of the vault,
+    /// pages included, into the config's `content_hash` with the same chain as
+    /// reveal_config. Lines can no longer be added once the first batch is hashed, and the
+    /// config is frozen after the vault's last line.
+    pub fn freeze_config<'info>(
+        ctx: Context<'_, '_, '_, 'info, FreezeConfig<'info>>,
+        batch_size: u32,
+    ) -> ProgramResult {
+        let config = &ctx.accounts.config;
+        if config.frozen {
+            return Err(ErrorCode::ConfigFrozen.into());
+        }
+
+        // Configs migrated from v1 have no extension to keep the hash in
+        let config_info = config.to_account_info();
+        if config.extension_len == 0 {
+            return Err(ErrorCode::ConfigNotFreezable.into());
+        }
+        let mut extension = get_config_extension(&config_info, config)?;
+
+        let items_available = ctx.accounts.nft_vault.data.items_available as usize;
+        if items_available == 0
+            || items_available < config.freeze_progress as usize
+            || items_available > get_total_config_capacity(config)
+        {
+            return Err(ErrorCode::ConfigNotComplete.into());
+        }
+
+        let end = std::cmp::min(
+            (config.freeze_progress as usize)
+                .checked_add(batch_size as usize)
+                .ok_or(ErrorCode::NumericalOverflowError)?,
+            items_available,
+        );
+
+        let mut hash = extension.content_hash;
+        for index in config.freeze_progress as usize..end {
+            let segment = get_config_segment(
+                &config_info,
+                config,
+                ctx.remaining_accounts,
+                ctx.program_id,
+                index,
+            )?;
+            if !is_config_line_loaded(&segment, index)? {
+                return Err(ErrorCode::ConfigNotComplete.into());
+            }
+            let data = segment.account.data.borrow();
+            let position =
+                segment.array_start + 4 + (index - segment.start_index) * segment.line_size;
+            hash = hash_config_line(&hash, &data[position..position + segment.line_size]);
+        }
+
+        extension.content_hash = hash;
+        set_config_extension(&config_info, config, &extension)?;
+
+        let config = &mut ctx.accounts.config;
+        config.freeze_progress = end as u32;
+        msg!("Hashed config lines up to {}", end);
+
+        if end == items_available {
+            msg!("Config frozen with {} lines", items_available);
+            config.frozen = true;
+        }
+
+        Ok(())
+    }
+
+    pub fn initialize_nft_vault(
+        ctx: Context<InitializeNftVault>,
+        bump: u8,
+        data: NftVaultData,
+    ) -> ProgramResult {
+        let nft_vault = &mut ctx.accounts.nft_vault;
+
+        if data.uuid.len() != 6 {
+            return Err(ErrorCode::UuidMustBeExactly6Length.into());
+        }
+        if let Some(auction) = &data.dutch_auction {
+            if auction.floor_price > auction.start_price || auction.decay_interval <= 0 {
+                return Err(ErrorCode::InvalidDutchAuction.into());
+            }
+        }
+        assert_valid_phases(&data)?;
+        if data.team_reserve > data.items_available {
+            return Err(ErrorCode::TeamReserveTooLarge.into());
+        }
+        if let Some(hidden_settings) = &data.hidden_settings {
+            assert_hidden_settings_fit(hidden_settings, data.items_available)?;
+            if !ctx.accounts.config.data.retain_authority || !ctx.accounts.config.data.is_mutable {
+                return Err(ErrorCode::HiddenSettingsConfigInvalid.into());
+            }
+        }
+        let mut remaining_accounts = ctx.remaining_accounts;
+        if let Some(master_mint) = data.print_master_mint {
+            if data.hidden_settings.is_some() {
+                return Err(ErrorCode::PrintModeInvalid.into());
+            }
+            let (source_edition, rest) = match remaining_accounts.split_last() {
+                Some(val) => val,
+                None => return Err(ErrorCode::PrintAccountsMissing.into()),
+            };
+            assert_print_supply(&master_mint, source_edition, data.items_available)?;
+            remaining_accounts = rest;
+        }
+
+        nft_vault.data = data;
+        nft_vault.wallet = *ctx.accounts.wallet.key;
+        nft_vault.authority = *ctx.accounts.authority.key;
+        nft_vault.config = ctx.accounts.config.key();
+        nft_vault.bump = bump;
+        nft_vault.version = NFT_VAULT_VERSION;
+        nft_vault.token_mint = get_wallet_token_mint(&ctx.accounts.wallet, remaining_accounts)?;
+        // The tax moves lamports to the wallet, which for spl vaults is a token account
+        if nft_vault.data.bot_tax.is_some() && nft_vault.token_mint.is_some() {
+            return Err(ErrorCode::BotTaxRequiresSolWallet.into());
+        }
+
+        // Hidden settings vaults only need their config lines by reveal time, and print
+        // vaults never read them
+        if nft_vault.data.hidden_settings.is_none() && nft_vault.data.print_master_mint.is_none() {
+            if get_total_config_count(&ctx.accounts.config)?
+                < nft_vault.data.items_available as usize
+            {
+                return Err(ErrorCode::ConfigLineMismatch.into());
+            }
+
+            let config_info = ctx.accounts.config.to_account_info();
+            let segment =
+                get_config_segment(&config_info, &ctx.accounts.config, &[], ctx.program_id, 0)?;
+            let _config_line = match get_config_line(&segment, 0) {
+                Ok(val) => val,
+                Err(_) => return Err(ErrorCode::ConfigMustHaveAtleastOneEntry.into()),
+            };
+        }
+
+        let config = &mut ctx.accounts.config;
+        config.vault_count = config
+            .vault_count
+            .checked_add(1)
+            .ok_or(ErrorCode::NumericalOverflowError)?;
+
+        Ok(())
+    }
+
+    /// Hashes the next `batch_size` config lines into the running reveal hash. Once every
+    /// line up to `items_available` is hashed, the result must match the hidden settings
+    /// commitment h_n, where h_0 = [0; 32] and h_(i+1) = keccak(h_i, line_i) over each line
+    /// as stored in the config. The config must be frozen over those lines, see
+    /// freeze_config, so they cannot change between the batches or after the reveal.
+    pub fn reveal_config<'info>(
+        ctx: Context<'_, '_, '_, 'info, RevealConfig<'info>>,
+        batch_size: u32,
+        restart: bool,
+    ) -> ProgramResult {
+        let nft_vault = &mut ctx.accounts.nft_vault;
+        let hidden_settings = match &nft_vault.data.hidden_settings {
+            Some(val) => val.clone(),
+            None => return Err(ErrorCode::HiddenSettingsNotSet.into()),
+        };
+
+        if nft_vault.revealed {
+            return Err(ErrorCode::NftVaultAlreadyRevealed.into());
+        }
+
+        let config = &ctx.accounts.config;
+        assert_reveal_bound(config, nft_vault.data.items_available)?;
+
+        // A config frozen over exactly the vault's items already has the hash at hand
+        if config.freeze_progress as u64 == nft_vault.data.items_available {
+            let content_hash =
+                get_config_extension(&config.to_account_info(), config)?.content_hash;
+            if content_hash != hidden_settings.hash {
+                return Err(ErrorCode::HiddenSettingsHashMismatch.into());
+            }
+            msg!("Frozen config matches the hidden settings hash");
+            nft_vault.reveal_progress = nft_vault.data.items_available;
+            nft_vault.reveal_hash = content_hash;
+            nft_vault.revealed = true;
+            return Ok(());
+        }
+
+        if restart {
+            nft_vault.reveal_progress = 0;
+            nft_vault.reveal_hash = [0u8; 32];
+        }
+
+        let config_info = ctx.accounts.config.to_account_info();
+        if get_total_config_count(&ctx.accounts.config)? < nft_vault.data.items_available as usize {
+            return Err(ErrorCode::ConfigLineMismatch.into());
+        }
+
+        let end = std::cmp::min(
+            nft_vault
+                .reveal_progress
+                .checked_add(batch_size as u64)
+                .ok_or(ErrorCode::NumericalOverflowError)?,
+            nft_vault.data.items_available,
+        );
+
+        let mut hash = nft_vault.reveal_hash;
+        for index in nft_vault.reveal_progress as usize..end as usize {
+            // Lines past the config itself are read from the pages in remaining accounts
+            let segment = get_config_segment(
+                &config_info,
+                &ctx.accounts.config,
+                ctx.remaining_accounts,
+                ctx.program_id,
+                index,
+            )?;
+            let data = segment.account.data.borrow();
+            let position =
+                segment.array_start + 4 + (index - segment.start_index) * segment.line_size;
+            hash = hash_config_line(&hash, &data[position..position + segment.line_size]);
+        }
+        nft_vault.reveal_hash = hash;
+        nft_vault.reveal_progress = end;
+        msg!("Hashed config lines up to {}", end);
+
+        if end == nft_vault.data.items_available {
+            if hash != hidden_settings.hash {
+                return Err(ErrorCode::HiddenSettingsHashMismatch.into());
+            }
+            msg!("Config lines match the hidden settings hash");
+            nft_vault.revealed = true;
+        }
+
+        Ok(())
+    }
+
+    pub fn reveal_nft<'info>(
+        ctx: Context<'_, '_, '_, 'info, RevealNft<'info>>,
+        index: u64,
+    ) -> ProgramResult {
+        let nft_vault = &ctx.accounts.nft_vault;
+        let hidden_settings = match &nft_vault.data.hidden_settings {
+            Some(val) => val,
+            None => return Err(ErrorCode::HiddenSettingsNotSet.into()),
+        };
+
+        if !nft_vault.revealed {
+            return Err(ErrorCode::NftVaultNotRevealedYet.into());
+        }
+
+        if index >= nft_vault.items_redeemed {
+            return Err(ErrorCode::IndexGreaterThanLength.into());
+        }
+
+        assert_owned_by(&ctx.accounts.metadata, &spl_token_metadata::id())?;
+        let metadata = Metadata::from_account_info(&ctx.accounts.metadata)?;
+        let expected_name = format!("{} #{}", hidden_settings.name, index + 1);
+        if metadata.update_authority != nft_vault.key()
+            || metadata.data.name.trim_matches(char::from(0)) != expected_name
+        {
+            return Err(ErrorCode::HiddenMetadataMismatch.into());
+        }
+
+        let config_info = ctx.accounts.config.to_account_info();
+        let segment = get_config_segment(
+            &config_info,
+            &ctx.accounts.config,
+            ctx.remaining_accounts,
+            ctx.program_id,
+            index as usize,
+        )?;
+        let config_line = get_config_line(&segment, index as usize)?;
+
+        let config_key = ctx.accounts.config.key();
+        let authority_seeds = [
+            PREFIX.as_bytes(),
+            config_key.as_ref(),
+            nft_vault.data.uuid.as_bytes(),
+            &[nft_vault.bump],
+        ];
+
+        invoke_signed(
+            &update_metadata_accounts(
+                *ctx.accounts.token_metadata_program.key,
+                *ctx.accounts.metadata.key,
+                nft_vault.key(),
+                Some(nft_vault.authority),
+                Some(Data {
+                    name: config_line.name,
+                    symbol: metadata.data.symbol,
+                    uri: config_line.uri,
+                    seller_fee_basis_points: metadata.data.seller_fee_basis_points,
+                    creators: metadata.data.creators,
+                }),
+                None,
+            ),
+            &[
+                ctx.accounts.token_metadata_program.clone(),
+                ctx.accounts.metadata.clone(),
+                nft_vault.to_account_info().clone(),
+            ],
+            &[&authority_seeds],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn close_nft_vault(ctx: Context<CloseNftVault>) -> ProgramResult {
+        assert_nft_vault_closable(&ctx.accounts.nft_vault, Clock::get()?.unix_timestamp)?;
+
+        let config = &mut ctx.accounts.config;
+        config.vault_count = config
+            .vault_count
+            .checked_sub(1)
+            .ok_or(ErrorCode::NumericalOverflowError)?;
+
+        let nft_vault_info = ctx.accounts.nft_vault.to_account_info();
+        let mut data = nft_vault_info.data.borrow_mut();
+        let len = data.len();
+        sol_memset(&mut data, 0, len);
+
+        msg!(
+            "Closing nft vault, sending {} lamports to recipient",
+            nft_vault_info.lamports()
+        );
+        Ok(())
+    }
+
+    pub fn close_config(ctx: Context<CloseConfig>) -> ProgramResult {
+        if ctx.accounts.config.vault_count > 0 {
+            return Err(ErrorCode::ConfigStillInUse.into());
+        }
+        if ctx.accounts.config.page_count > 0 {
+            return Err(ErrorCode::ConfigHasPages.into());
+        }
+
+        let config_info = ctx.accounts.config.to_account_info();
+        let mut data = config_info.data.borrow_mut();
+        let len = data.len();
+        sol_memset(&mut data, 0, len);
+
+        msg!(
+            "Closing config, sending {} lamports to recipient",
+            config_info.lamports()
+        );
+        Ok(())
+    }
+
+    /// Unlinks and closes the last page of a config no open nft vault mints from.
+    pub fn close_config_page(ctx: Context<CloseConfigPage>) -> ProgramResult {
+        let config = &mut ctx.accounts.config;
+        let page_info = &ctx.accounts.config_page;
+
+        if config.vault_count > 0 {
+            return Err(ErrorCode::ConfigStillInUse.into());
+        }
+        if config.frozen || config.freeze_progress > 0 {
+            return Err(ErrorCode::ConfigFrozen.into());
+        }
+
+        assert_owned_by(page_info, ctx.program_id)?;
+        let page = ConfigPage::try_deserialize(&mut &page_info.data.borrow()[..])?;
+        if page.config != config.key() || page.page_index + 1 != config.page_count {
+            return Err(ErrorCode::ConfigPageMismatch.into());
+        }
+        let page_count = get_config_count_at(&page_info.data.borrow(), CONFIG_PAGE_ARRAY_START)?;
+
+        config.page_count -= 1;
+        config.page_capacity = config
+            .page_capacity
+            .checked_sub(page.max_number_of_lines)
+            .ok_or(ErrorCode::NumericalOverflowError)?;
+        config.page_line_count = config
+            .page_line_count
+            .checked_sub(page_count as u32)
+            .ok_or(ErrorCode::NumericalOverflowError)?;
+
+        msg!(
+            "Closing config page {}, sending {} lamports to recipient",
+            page.page_index,
+            page_info.lamports()
+        );
+        close_program_account(page_info, &ctx.accounts.recipient)
+    }
+
+    /// Rewrites a v1 config into the current layout. Lines stay at CONFIG_ARRAY_START, so
+    /// only the header is rewritten and the config keeps its account: the new header
+    /// fields fit in the room v1 already kept before the lines.
+    ///
+    /// v1 configs did not count their vaults, so every v1 nft vault of the config is
+    /// passed as a remaining account, migrated along with it and counted in
+    /// `vault_count`. Otherwise close_config would let the config go while its vaults
+    /// still need it to migrate or close. Vaults that do not fit in the transaction can
+    /// follow through migrate_nft_vault before the config is closed.
+    pub fn migrate_config<'info>(
+        ctx: Context<'_, '_, '_, 'info, MigrateConfig<'info>>,
+    ) -> ProgramResult {
+        let config_info = &ctx.accounts.config;
+        let mut data = config_info.data.borrow_mut();
+        if data.len() < CONFIG_ARRAY_START || data[..8] != Config::discriminator() {
+            return Err(ErrorCode::AccountNotMigratable.into());
+        }
+
+        // v1 never wrote past its header, so the current fields read as zeroes there
+        let current = Config::try_deserialize(&mut &data[..])?;
+        if current.version >= CONFIG_VERSION {
+            return Err(ErrorCode::AccountAlreadyMigrated.into());
+        }
+
+        let v1 = ConfigV1::deserialize(&mut &data[8..])?;
+        if v1.authority != *ctx.accounts.authority.key {
+            return Err(ErrorCode::MigrationAuthorityMismatch.into());
+        }
+
+        let mut config = migrate_config_v1(v1);
+        for nft_vault_info in ctx.remaining_accounts {
+            assert_owned_by(nft_vault_info, ctx.program_id)?;
+            migrate_nft_vault_account(nft_vault_info, config_info.key, ctx.accounts.authority.key)?;
+            config.vault_count = config
+                .vault_count
+                .checked_add(1)
+                .ok_or(ErrorCode::NumericalOverflowError)?;
+        }
+
+        sol_memset(&mut data[8..CONFIG_ARRAY_START], 0, CONFIG_ARRAY_START - 8);
+        let mut writer: &mut [u8] = &mut data[..CONFIG_ARRAY_START];
+        config
+            .try_serialize(&mut writer)
+            .map_err(|_| ErrorCode::AccountNotMigratable)?;
+        msg!(
+            "Config migrated to version {} with {} nft vaults",
+            CONFIG_VERSION,
+            config.vault_count
+        );
+
+        Ok(())
+    }
+
+    /// Rewrites a v1 nft vault left out of migrate_config into the current layout and
+    /// counts it on its config. Accounts cannot be resized on this runtime, so the vault
+    /// keeps its NFT_VAULT_V1_SIZE account: the migrated layout fits, but updates that
+    /// would outgrow it, such as several payment options or sale phases, fail with
+    /// NftVaultAccountTooSmall.
+    pub fn migrate_nft_vault(ctx: Context<MigrateNftVault>) -> ProgramResult {
+        let config = &mut ctx.accounts.config;
+        migrate_nft_vault_account(
+            &ctx.accounts.nft_vault,
+            &config.key(),
+            ctx.accounts.authority.key,
+        )?;
+
+        config.vault_count = config
+            .vault_count
+            .checked_add(1)
+            .ok_or(ErrorCode::NumericalOverflowError)?;
+
+        Ok(())
+    }
+}
+
+/// Returns the mint the vault charges in when paying into `wallet`: the first remaining
+/// account if there is one, which must be the mint of the token account `wallet`,
+/// otherwise none and the vault charges sol.
+pub fn get_wallet_token_mint(
+    wallet: &AccountInfo,
+    remaining_accounts: &[AccountInfo],
+) -> core::result::Result<Option<Pubkey>, ProgramError> {
+    let token_mint_info = match remaining_accounts.first() {
+        Some(val) => val,
+        None => return Ok(None),
+    };
+    let _token_mint: Mint = assert_initialized(token_mint_info)?;
+    let token_account: Account = assert_initialized(wallet)?;
+
+    assert_owned_by(token_mint_info, &spl_token::id())?;
+    assert_owned_by(wallet, &spl_token::id())?;
+
+    if token_account.mint != *token_mint_info.key {
+        return Err(ErrorCode::MintMismatch.into());
+    }
+
+    Ok(Some(*token_mint_info.key))
+}
+
+/// Rejects `count` more prints than the master edition of `master_mint` has left.
+pub fn assert_print_supply(
+    master_mint: &Pubkey,
+    source_edition: &AccountInfo,
+    count: u64,
+) -> ProgramResult {
+    if *source_edition.key != find_master_edition_address(master_mint) {
+        return Err(ErrorCode::DerivedKeyInvalid.into());
+    }
+    assert_owned_by(source_edition, &spl_token_metadata::id())?;
+    let master_edition = get_master_edition(source_edition)?;
+    if let Some(max_supply) = master_edition.max_supply() {
+        if count > max_supply.saturating_sub(master_edition.supply()) {
+            return Err(ErrorCode::PrintSupplyExceeded.into());
+        }
+    }
+    Ok(())
+}
+
+/// Next link of the chain freeze_config and reveal_config hash config lines into.
+pub fn hash_config_line(hash: &[u8; 32], line: &[u8]) -> [u8; 32] {
+    keccak::hashv(&[hash, line]).0
+}
+
+/// A reveal only binds the vault to its hidden settings commitment if the lines it hashes
+/// can no longer change, so the config must be frozen over all of the vault's items.
+pub fn assert_reveal_bound(config: &Config, items_available: u64) -> ProgramResult {
+    if !config.frozen {
+        return Err(ErrorCode::ConfigNotFrozen.into());
+    }
+    if items_available > config.freeze_progress as u64 {
+        return Err(ErrorCode::ItemsAvailableExceedFrozenLines.into());
+    }
+    Ok(())
+}
+
+/// Hidden names get ` #N` appended, so they must fit with the longest item number.
+pub fn assert_hidden_settings_fit(
+    hidden_settings: &HiddenSettings,
+    items_available: u64,
+) -> ProgramResult {
+    let max_number_len = items_available.to_string().len();
+    if hidden_settings.name.len() + 2 + max_number_len > MAX_NAME_LENGTH
+        || hidden_settings.uri.len() > MAX_URI_LENGTH
+    {
+        return Err(ErrorCode::HiddenSettingsTooLong.into());
+    }
+    Ok(())
+}
+
+/// Rejects mints while the vault is paused, after its end date, or before go live
+/// for payers that are not allowlisted. With sale phases, the active phase's allowlist
+/// applies instead. The authority mints its reserve through team_mint.
+pub fn assert_mint_allowed(
+    nft_vault: &NftVault,
+    payer: &Pubkey,
+    unix_timestamp: i64,
+    proof: &Option<Vec<[u8; 32]>>,
+) -> ProgramResult {
+    if nft_vault.paused {
+        return Err(ErrorCode::NftVaultPaused.into());
+    }
+
+    if let Some(end_date) = nft_vault.end_date {
+        if unix_timestamp >= end_date {
+            return Err(ErrorCode::NftVaultSaleEnded.into());
+        }
+    }
+
+    // Sale phases replace the go live date and the vault wide allowlist
+    if !nft_vault.data.phases.is_empty() {
+        return match get_active_phase(&nft_vault.data, unix_timestamp) {
+            None => Err(ErrorCode::NftVaultNotLiveYet.into()),
+            Some((_, phase)) => match phase.allowlist_root {
+                Some(root) => assert_allowlisted(Some(root), proof, payer),
+                None => Ok(()),
+            },
+        };
+    }
+
+    match nft_vault.data.go_live_date {
+        None => {
+            assert_allowlisted(nft_vault.allowlist_root, proof, payer)?;
+        }
+        Some(val) => {
+            if unix_timestamp < val {
+                assert_allowlisted(nft_vault.allowlist_root, proof, payer)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Public mints stop `team_reserve` items short of `items_available`, minus what
+/// team_mint has taken already.
+pub fn assert_public_supply(nft_vault: &NftVault, count: u64) -> ProgramResult {
+    let public_minted = nft_vault
+        .items_redeemed
+        .checked_sub(nft_vault.team_minted)
+        .ok_or(ErrorCode::NumericalOverflowError)?;
+    let public_available = nft_vault
+        .data
+        .items_available
+        .checked_sub(nft_vault.data.team_reserve)
+        .ok_or(ErrorCode::NumericalOverflowError)?;
+    let public_after = public_minted
+        .checked_add(count)
+        .ok_or(ErrorCode::NumericalOverflowError)?;
+    if public_after > public_available {
+        return Err(ErrorCode::NftVaultEmpty.into());
+    }
+    Ok(())
+}
+
+/// A vault can only close before its first mint, once sold out or after its end date.
+/// Hidden items still need the vault to be revealed, and a raffle must be closed first
+/// since its tickets need the vault to be redeemed or refunded.
+pub fn assert_nft_vault_closable(nft_vault: &NftVault, unix_timestamp: i64) -> ProgramResult {
+    if nft_vault.raffle.is_some() {
+        return Err(ErrorCode::NftVaultHasRaffle.into());
+    }
+    if nft_vault.items_redeemed == 0 {
+        return Ok(());
+    }
+
+    if nft_vault.data.hidden_settings.is_some() && !nft_vault.revealed {
+        return Err(ErrorCode::NftVaultNotRevealedYet.into());
+    }
+    let sold_out = nft_vault.items_redeemed >= nft_vault.data.items_available;
+    let sale_ended = match nft_vault.end_date {
+        Some(end_date) => unix_timestamp >= end_date,
+        None => false,
+    };
+    if !sold_out && !sale_ended {
+        return Err(ErrorCode::NftVaultSaleInProgress.into());
+    }
+    Ok(())
+}
+
+/// Rejects an `items_available` whose public part, after the team reserve, cannot cover
+/// the public mints so far and the raffle winners still to redeem.
+pub fn assert_supply_covers(
+    nft_vault: &NftVault,
+    items_available: u64,
+    unredeemed_winners: u32,
+) -> ProgramResult {
+    let public_minted = nft_vault
+        .items_redeemed
+        .checked_sub(nft_vault.team_minted)
+        .ok_or(ErrorCode::NumericalOverflowError)?;
+    let public_available = items_available
+        .checked_sub(nft_vault.data.team_reserve)
+        .ok_or(ErrorCode::TeamReserveTooLarge)?;
+    let public_owed = public_minted
+        .checked_add(unredeemed_winners as u64)
+        .ok_or(ErrorCode::NumericalOverflowError)?;
+    if public_available < public_owed {
+        return Err(ErrorCode::ItemsAvailableBelowOwed.into());
+    }
+    Ok(())
+}
+
+pub struct BotTaxParams<'a, 'b> {
+    pub nft_vault: &'b NftVault,
+    pub payer: AccountInfo<'a>,
+    pub wallet: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+    pub error: ProgramError,
+}
+
+/// With a bot tax set, turns a mint before go live, on an empty vault or with the wrong
+/// payment mint into a successful instruction that only moves the tax to the wallet.
+/// Any other error, or any error without a bot tax, is returned as is.
+pub fn charge_bot_tax(params: BotTaxParams<'_, '_>) -> ProgramResult {
+    let BotTaxParams {
+        nft_vault,
+        payer,
+        wallet,
+        system_program,
+        error,
+    } = params;
+
+    let tax = match nft_vault.data.bot_tax {
+        Some(val) => val,
+        None => return Err(error),
+    };
+    let taxable: [ProgramError; 3] = [
+        ErrorCode::NftVaultNotLiveYet.into(),
+        ErrorCode::NftVaultEmpty.into(),
+        ErrorCode::MintMismatch.into(),
+    ];
+    if !taxable.contains(&error) {
+        return Err(error);
+    }
+
+    msg!("Invalid mint, charging bot tax {}", tax);
+    invoke(
+        &system_instruction::transfer(payer.key, wallet.key, tax),
+        &[payer, wallet, system_program],
+    )?;
+
+    Ok(())
+}
+
+pub struct WalletMintParams<'a, 'b> {
+    pub program_id: &'b Pubkey,
+    pub nft_vault_key: Pubkey,
+    pub max_per_wallet: u64,
+    pub phase: Option<i64>,
+    pub count: u64,
+    pub counter: AccountInfo<'a>,
+    pub minter: Pubkey,
+    pub payer: AccountInfo<'a>,
+    pub rent: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+}
+
+pub struct RedeemTicketParams<'a: 'b, 'b> {
+    pub program_id: &'b Pubkey,
+    pub nft_vault: &'b NftVault,
+    pub config: &'b Config,
+    pub raffle_key: Pubkey,
+    pub minter: Pubkey,
+    pub wallet: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    pub remaining_accounts: &'b [AccountInfo<'a>],
+}
+
+/// Pays a winning ticket's escrow to the vault wallet, to the config creators first when
+/// the primary sale is split, and closes the ticket to its buyer. The raffle, the ticket
+/// bought by the minter, the minter's wallet, which gets the ticket rent back, and for spl
+/// vaults the escrow token account are the first remaining accounts, followed by the
+/// creator payout accounts. Returns the ticket price and its spl mint, if
+/// any.
+pub fn redeem_raffle_ticket(
+    params: RedeemTicketParams<'_, '_>,
+) -> core::result::Result<(u64, Option<Pubkey>), ProgramError> {
+    let RedeemTicketParams {
+        program_id,
+        nft_vault,
+        config,
+        raffle_key,
+        minter,
+        wallet,
+        token_program,
+        remaining_accounts,
+    } = params;
+
+    let mut remaining_accounts = remaining_accounts;
+    let raffle_accounts =
+        take_accounts(&mut remaining_accounts, 3, ErrorCode::RaffleAccountsMissing)?;
+    let raffle_info = &raffle_accounts[0];
+    let ticket_info = &raffle_accounts[1];
+    let buyer_info = &raffle_accounts[2];
+    if *raffle_info.key != raffle_key {
+        return Err(ErrorCode::RaffleMismatch.into());
+    }
+    assert_owned_by(raffle_info, program_id)?;
+    assert_owned_by(ticket_info, program_id)?;
+
+    let mut raffle = Raffle::try_deserialize(&mut &raffle_info.data.borrow()[..])?;
+    let ticket = RaffleTicket::try_deserialize(&mut &ticket_info.data.borrow()[..])?;
+    if ticket.raffle != raffle_key || ticket.buyer != minter || *buyer_info.key != minter {
+        return Err(ErrorCode::RaffleTicketMismatch.into());
+    }
+    if !is_raffle_winner(&raffle, ticket.index) {
+        return Err(ErrorCode::NotRaffleWinner.into());
+    }
+    let escrow_info = match raffle.escrow {
+        Some(_) => Some(
+            take_accounts(&mut remaining_accounts, 1, ErrorCode::RaffleAccountsMissing)?[0].clone(),
+        ),
+        None => None,
+    };
+
+    let creator_payouts = if nft_vault.data.split_primary_sale {
+        get_creator_payouts(
+            &config.data.creators,
+            remaining_accounts,
+            ticket.price,
+            nft_vault.token_mint,
+        )?
+    } else {
+        vec![]
+    };
+    let mut wallet_amount = ticket.price;
+    for (_, amount) in &creator_payouts {
+        wallet_amount = wallet_amount
+            .checked_sub(*amount)
+            .ok_or(ErrorCode::NumericalOverflowError)?;
+    }
+
+    msg!("Redeeming raffle ticket {}", ticket.index);
+    match (raffle.escrow, escrow_info) {
+        (Some(escrow), Some(escrow_info)) => {
+            if *escrow_info.key != escrow {
+                return Err(ErrorCode::RaffleMismatch.into());
+            }
+
+            let nft_vault_key = raffle.nft_vault;
+            let raffle_seeds = [
+                PREFIX.as_bytes(),
+                nft_vault_key.as_ref(),
+                RAFFLE.as_bytes(),
+                &[raffle.bump],
+            ];
+            let payouts = creator_payouts
+                .iter()
+                .map(|(payout_info, amount)| ((*payout_info).clone(), *amount))
+                .chain(std::iter::once((wallet.clone(), wallet_amount)));
+            for (destination, amount) in payouts {
+                spl_token_transfer(TokenTransferParams {
+                    source: escrow_info.clone(),
+                    destination,
+                    authority: raffle_info.clone(),
+                    authority_signer_seeds: &raffle_seeds,
+                    token_program: token_program.clone(),
+                    amount,
+                })?;
+            }
+        }
+        _ => {
+            let ticket_lamports = ticket_info.lamports();
+            **ticket_info.lamports.borrow_mut() = ticket_lamports
+                .checked_sub(ticket.price)
+                .ok_or(ErrorCode::NumericalOverflowError)?;
+            let payouts = creator_payouts
+                .iter()
+                .map(|(payout_info, amount)| (*payout_info, *amount))
+                .chain(std::iter::once((&wallet, wallet_amount)));
+            for (destination, amount) in payouts {
+                **destination.lamports.borrow_mut() = destination
+                    .lamports()
+                    .checked_add(amount)
+                    .ok_or(ErrorCode::NumericalOverflowError)?;
+            }
+        }
+    }
+    close_program_account(ticket_info, buyer_info)?;
+
+    raffle.winners_settled = raffle
+        .winners_settled
+        .checked_add(1)
+        .ok_or(ErrorCode::NumericalOverflowError)?;
+    raffle.tickets_settled = raffle
+        .tickets_settled
+        .checked_add(1)
+        .ok_or(ErrorCode::NumericalOverflowError)?;
+    raffle.try_serialize(&mut &mut raffle_info.data.borrow_mut()[..])?;
+
+    Ok((ticket.price, nft_vault.token_mint))
+}
+
+/// Adds `count` to the minter's WalletMintCounter, creating it on first mint at the
+/// payer's expense. Sale phases keep a separate counter each, with the phase start time
+/// (i64 le) as an extra seed.
+pub fn record_wallet_mints(params: WalletMintParams<'_, '_>) -> ProgramResult {
+    let WalletMintParams {
+        program_id,
+        nft_vault_key,
+        max_per_wallet,
+        phase,
+        count,
+        counter: counter_info,
+        minter,
+        payer,
+        rent,
+        system_program,
+    } = params;
+
+    let phase_bytes = phase.map(|start_time| start_time.to_le_bytes());
+    let mut seeds: Vec<&[u8]> = vec![PREFIX.as_bytes(), nft_vault_key.as_ref(), minter.as_ref()];
+    if let Some(bytes) = &phase_bytes {
+        seeds.push(bytes);
+    }
+    let (counter_key, counter_bump) = Pubkey::find_program_address(&seeds, program_id);
+    if counter_key != *counter_info.key {
+        return Err(ErrorCode::DerivedKeyInvalid.into());
+    }
+
+    let mut counter = if counter_info.data_is_empty() {
+        let bump_bytes = [counter_bump];
+        seeds.push(&bump_bytes);
+        create_or_allocate_account_raw(
+            *program_id,
+            &counter_info,
+            &rent,
+            &system_program,
+            &payer,
+            WALLET_MINT_COUNTER_SIZE,
+            &seeds,
+        )?;
+        WalletMintCounter {
+            nft_vault: nft_vault_key,
+            wallet: minter,
+            count: 0,
+            bump: counter_bump,
+        }
+    } else {
+        assert_owned_by(&counter_info, program_id)?;
+        WalletMintCounter::try_deserialize(&mut &counter_info.data.borrow()[..])?
+    };
+
+    counter.count = counter
+        .count
+        .checked_add(count)
+        .ok_or(ErrorCode::NumericalOverflowError)?;
+    if counter.count > max_per_wallet {
+        return Err(ErrorCode::WalletMintLimitReached.into());
+    }
+    counter.try_serialize(&mut &mut counter_info.data.borrow_mut()[..])?;
+
+    Ok(())
+}
+
+/// Takes the next `count` accounts off the front of `accounts`, failing with `error` when
+/// fewer are left.
+pub fn take_accounts<'a, 'b>(
+    accounts: &mut &'b [AccountInfo<'a>],
+    count: usize,
+    error: ErrorCode,
+) -> core::result::Result<&'b [AccountInfo<'a>], ProgramError> {
+    if accounts.len() < count {
+        return Err(error.into());
+    }
+    let (taken, rest) = accounts.split_at(count);
+    *accounts = rest;
+    Ok(taken)
+}
+
+/// Rejects a payment token account that matches neither a payment option nor the vault's
+/// token mint, given the payment accounts charge_for_mint takes.
+pub fn assert_payment_mint(
+    nft_vault: &NftVault,
+    payment_accounts: &[AccountInfo],
+) -> ProgramResult {
+    let mint = match nft_vault.token_mint {
+        Some(val) => val,
+        None => return Ok(()),
+    };
+    if get_payment_option(&nft_vault.payment_options, payment_accounts.first()).is_some() {
+        return Ok(());
+    }
+
+    let token_account_info = match payment_accounts.first() {
+        Some(val) => val,
+        None => return Err(ErrorCode::PaymentAccountsMissing.into()),
+    };
+    assert_owned_by(token_account_info, &spl_token::id())?;
+    let token_account: Account = assert_initialized(token_account_info)?;
+    if token_account.mint != mint {
+        return Err(ErrorCode::MintMismatch.into());
+    }
+    Ok(())
+}
+
+/// Takes the MintReceipt account off the front of `accounts` when the vault records
+/// receipts.
+pub fn take_receipt_account<'a>(
+    nft_vault: &NftVault,
+    accounts: &mut &[AccountInfo<'a>],
+) -> core::result::Result<Option<AccountInfo<'a>>, ProgramError> {
+    if !nft_vault.data.record_receipts {
+        return Ok(None);
+    }
+    let receipt = take_accounts(accounts, 1, ErrorCode::ReceiptAccountMissing)?;
+    Ok(Some(receipt[0].clone()))
+}
+
+/// Whether the vault mints config lines in random order. Configs from v1 have no room for
+/// the used bitmask and keep minting their lines in order, as v1 vaults always did.
+pub fn draws_random_lines(nft_vault: &NftVault, config: &Config) -> bool {
+    nft_vault.data.hidden_settings.is_none()
+        && nft_vault.data.print_master_mint.is_none()
+        && config.extension_len > 0
+}
+
+/// Takes the instructions sysvar off the front of `accounts` when the vault draws its
+/// lines at random, see assert_draw_unobserved.
+pub fn take_draw_account<'a>(
+    nft_vault: &NftVault,
+    config: &Config,
+    accounts: &mut &[AccountInfo<'a>],
+) -> core::result::Result<Option<AccountInfo<'a>>, ProgramError> {
+    if !draws_random_lines(nft_vault, config) {
+        return Ok(None);
+    }
+    let sysvar = take_accounts(accounts, 1, ErrorCode::DrawAccountMissing)?;
+    Ok(Some(sysvar[0].clone()))
+}
+
+/// Takes every page linked to the config off the front of `accounts`, in page order, when
+/// the vault mints config lines. Random draws may land on any page and write its used
+/// bitmask, so the pages must be writable.
+pub fn take_config_pages<'a, 'b>(
+    nft_vault: &NftVault,
+    config: &Config,
+    accounts: &mut &'b [AccountInfo<'a>],
+) -> core::result::Result<&'b [AccountInfo<'a>], ProgramError> {
+    if nft_vault.data.hidden_settings.is_some() || nft_vault.data.print_master_mint.is_some() {
+        return Ok(&[]);
+    }
+    let pages = take_accounts(
+        accounts,
+        config.page_count as usize,
+        ErrorCode::ConfigPageMissing,
+    )?;
+    if pages.iter().any(|page| !page.is_writable) {
+        return Err(ErrorCode::ConfigPageNotWritable.into());
+    }
+    Ok(pages)
+}
+
+/// The slot hash is known to the minter, so a program calling this one, or a later
+/// instruction in the transaction, could inspect the drawn line and revert. Random draws
+/// must therefore come from the last top level instruction of the transaction.
+pub fn assert_draw_unobserved(
+    program_id: &Pubkey,
+    instruction_sysvar: &AccountInfo,
+) -> ProgramResult {
+    if *instruction_sysvar.key != sysvar::instructions::id() {
+        return Err(ErrorCode::DrawAccountMissing.into());
+    }
+    let data = instruction_sysvar.data.borrow();
+    let current_index = sysvar::instructions::load_current_index(&data) as usize;
+    match sysvar::instructions::load_instruction_at(current_index, &data) {
+        Ok(instruction) if instruction.program_id == *program_id => {}
+        _ => return Err(ErrorCode::DrawObservable.into()),
+    }
+    if sysvar::instructions::load_instruction_at(current_index + 1, &data).is_ok() {
+        return Err(ErrorCode::DrawObservable.into());
+    }
+    Ok(())
+}
+
+/// Print vaults take the master metadata, master edition and the vault's master token
+/// account as the last three of `accounts`.
+pub fn split_print_accounts<'a, 'b>(
+    nft_vault: &NftVault,
+    accounts: &'b [AccountInfo<'a>],
+) -> core::result::Result<(&'b [AccountInfo<'a>], &'b [AccountInfo<'a>]), ProgramError> {
+    if nft_vault.data.print_master_mint.is_none() {
+        return Ok((accounts, &[]));
+    }
+    if accounts.len() < 3 {
+        return Err(ErrorCode::PrintAccountsMissing.into());
+    }
+    Ok(accounts.split_at(accounts.len() - 3))
+}
+
+/// Single mints from a print vault end with the edition marker of the printed edition,
+/// after the print accounts. Batches take one marker per item instead.
+pub fn split_edition_marker<'a, 'b>(
+    nft_vault: &NftVault,
+    accounts: &'b [AccountInfo<'a>],
+) -> core::result::Result<(&'b [AccountInfo<'a>], Option<AccountInfo<'a>>), ProgramError> {
+    if nft_vault.data.print_master_mint.is_none() {
+        return Ok((accounts, None));
+    }
+    match accounts.split_last() {
+        Some((marker, rest)) => Ok((rest, Some(marker.clone()))),
+        None => Err(ErrorCode::PrintAccountsMissing.into()),
+    }
+}
+
+pub fn find_master_edition_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            spl_token_metadata::state::PREFIX.as_bytes(),
+            spl_token_metadata::id().as_ref(),
+            mint.as_ref(),
+            spl_token_metadata::state::EDITION.as_bytes(),
+        ],
+        &spl_token_metadata::id(),
+    )
+    .0
+}
+
+pub struct RecipientMintParams<'a> {
+    pub recipient: Pubkey,
+    pub recipient_info: AccountInfo<'a>,
+    pub recipient_token_account: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub mint_authority: AccountInfo<'a>,
+    pub payer: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    pub associated_token_program: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+    pub rent: AccountInfo<'a>,
+}
+
+/// Mints the single token of a fresh mint into the recipient's associated token account,
+/// creating that account first when needed.
+pub fn mint_to_recipient(params: RecipientMintParams<'_>) -> ProgramResult {
+    let RecipientMintParams {
+        recipient,
+        recipient_info,
+        recipient_token_account,
+        mint,
+        mint_authority,
+        payer,
+        token_program,
+        associated_token_program,
+        system_program,
+        rent,
+    } = params;
+
+    if *associated_token_program.key != ASSOCIATED_TOKEN_PROGRAM_ID {
+        return Err(ErrorCode::RecipientAccountsMissing.into());
+    }
+    if *recipient_info.key != recipient {
+        return Err(ErrorCode::RecipientMismatch.into());
+    }
+    if *recipient_token_account.key != get_associated_token_address(&recipient, mint.key) {
+        return Err(ErrorCode::DerivedKeyInvalid.into());
+    }
+
+    assert_owned_by(&mint, &spl_token::id())?;
+    let mint_state: Mint = assert_initialized(&mint)?;
+    if mint_state.supply != 0 {
+        return Err(ErrorCode::MintAlreadyHasSupply.into());
+    }
+
+    if recipient_token_account.data_is_empty() {
+        create_associated_token_account(
+            payer,
+            recipient_token_account.clone(),
+            recipient_info,
+            mint.clone(),
+            system_program,
+            token_program.clone(),
+            associated_token_program,
+            rent,
+        )?;
+    }
+
+    msg!("Minting to {}", recipient);
+    spl_token_mint_to(TokenMintToParams {
+        mint,
+        destination: recipient_token_account,
+        amount: 1,
+        authority: mint_authority,
+        authority_signer_seeds: &[],
+        token_program,
+    })
+}
+
+pub struct CosignParams<'a: 'b, 'b> {
+    pub program_id: &'b Pubkey,
+    pub nft_vault_key: Pubkey,
+    pub cosigner: Pubkey,
+    pub nonce_account: AccountInfo<'a>,
+    pub payer: AccountInfo<'a>,
+    pub instruction_sysvar_account: AccountInfo<'a>,
+    pub rent: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+    pub unix_timestamp: i64,
+}
+
+/// Requires the instruction right before this one to be an ed25519 signature by the
+/// cosigner over `nft_vault || payer || nonce (u64 le) || expiry (i64 le)`. The nonce is
+/// then recorded in a CosignNonce account so the same approval cannot mint twice.
+pub fn assert_cosigned(params: CosignParams<'_, '_>) -> ProgramResult {
+    let CosignParams {
+        program_id,
+        nft_vault_key,
+        cosigner,
+        nonce_account,
+        payer,
+        instruction_sysvar_account,
+        rent,
+        system_program,
+        unix_timestamp,
+    } = params;
+
+    if *instruction_sysvar_account.key != sysvar::instructions::id() {
+        return Err(ErrorCode::CosignAccountsMissing.into());
+    }
+    let message = {
+        let instruction_sysvar = instruction_sysvar_account.data.borrow();
+        let current_index = sysvar::instructions::load_current_index(&instruction_sysvar);
+        if current_index == 0 {
+            return Err(ErrorCode::CosignatureMissing.into());
+        }
+        get_ed25519_message(&instruction_sysvar, current_index as usize - 1, &cosigner)?
+    };
+
+    if message.len() != COSIGN_MESSAGE_LEN {
+        return Err(ErrorCode::CosignatureInvalid.into());
+    }
+    if message[0..32] != nft_vault_key.to_bytes() || message[32..64] != payer.key.to_bytes() {
+        return Err(ErrorCode::CosignatureInvalid.into());
+    }
+    let nonce = u64::from_le_bytes(*array_ref![message, 64, 8]);
+    let expiry = i64::from_le_bytes(*array_ref![message, 72, 8]);
+    if unix_timestamp > expiry {
+        return Err(ErrorCode::CosignatureExpired.into());
+    }
+
+    let nonce_bytes = nonce.to_le_bytes();
+    let (nonce_key, nonce_bump) = Pubkey::find_program_address(
+        &[
+            PREFIX.as_bytes(),
+            nft_vault_key.as_ref(),
+            NONCE.as_bytes(),
+            &nonce_bytes,
+        ],
+        program_id,
+    );
+    if nonce_key != *nonce_account.key {
+        return Err(ErrorCode::DerivedKeyInvalid.into());
+    }
+    if !nonce_account.data_is_empty() {
+        return Err(ErrorCode::CosignNonceUsed.into());
+    }
+    create_or_allocate_account_raw(
+        *program_id,
+        &nonce_account,
+        &rent,
+        &system_program,
+        &payer,
+        COSIGN_NONCE_SIZE,
+        &[
+            PREFIX.as_bytes(),
+            nft_vault_key.as_ref(),
+            NONCE.as_bytes(),
+            &nonce_bytes,
+            &[nonce_bump],
+        ],
+    )?;
+    CosignNonce {
+        nft_vault: nft_vault_key,
+        nonce,
+        bump: nonce_bump,
+    }
+    .try_serialize(&mut &mut nonce_account.data.borrow_mut()[..])?;
+
+    Ok(())
+}
+
+pub struct ChargeParams<'a: 'b, 'b> {
+    pub nft_vault: &'b NftVault,
+    pub config: &'b Config,
+    pub minter: Pubkey,
+    pub payer: AccountInfo<'a>,
+    pub wallet: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+    pub remaining_accounts: &'b [AccountInfo<'a>],
+    pub unix_timestamp: i64,
+    pub count: u64,
+}
+
+/// Checks that the minter passes the token gate and charges `count` times the mint
+/// price, paying the config creators first when the primary sale is split. Returns the
+/// price of one item and the spl mint it was paid in, if any.
+pub fn charge_for_mint(
+    params: ChargeParams<'_, '_>,
+) -> core::result::Result<(u64, Option<Pubkey>), ProgramError> {
+    let ChargeParams {
+        nft_vault,
+        config,
+        minter,
+        payer,
+        wallet,
+        token_program,
+        system_program,
+        remaining_accounts,
+        unix_timestamp,
+        count,
+    } = params;
+
+    let payment_option = get_payment_option(&nft_vault.payment_options, remaining_accounts.first());
+
+    // remaining_accounts holds the spl payment accounts first, then the token gate
+    // accounts, then the creator payout accounts
+    let mut remaining_accounts = remaining_accounts;
+    let payment_accounts = if payment_option.is_some() {
+        take_accounts(
+            &mut remaining_accounts,
+            3,
+            ErrorCode::PaymentOptionAccountsMissing,
+        )?
+    } else if nft_vault.token_mint.is_some() {
+        take_accounts(
+            &mut remaining_accounts,
+            2,
+            ErrorCode::PaymentAccountsMissing,
+        )?
+    } else {
+        &[]
+    };
+
+    if let Some(creator) = nft_vault.data.token_gate_creator {
+        let gate_accounts = take_accounts(
+            &mut remaining_accounts,
+            2,
+            ErrorCode::TokenGateAccountsMissing,
+        )?;
+        assert_holds_verified_nft(&minter, &gate_accounts[0], &gate_accounts[1], &creator)?;
+    }
+
+    let unit_price = match &payment_option {
+        Some(val) => val.price,
+        None => get_mint_price(&nft_vault.data, unix_timestamp)?,
+    };
+    let price = unit_price
+        .checked_mul(count)
+        .ok_or(ErrorCode::NumericalOverflowError)?;
+    msg!("Charging price {}", price);
+
+    let payment_mint = match &payment_option {
+        Some(val) => Some(val.mint),
+        None => nft_vault.token_mint,
+    };
+    let creator_payouts = if nft_vault.data.split_primary_sale {
+        get_creator_payouts(
+            &config.data.creators,
+            remaining_accounts,
+            price,
+            payment_mint,
+        )?
+    } else {
+        vec![]
+    };
+    let mut wallet_amount = price;
+    for (_, amount) in &creator_payouts {
+        wallet_amount = wallet_amount
+            .checked_sub(*amount)
+            .ok_or(ErrorCode::NumericalOverflowError)?;
+    }
+
+    if let Some(payment_option) = &payment_option {
+        let token_account_info = &payment_accounts[0];
+        let transfer_authority_info = &payment_accounts[1];
+        let treasury_info = &payment_accounts[2];
+        let token_account: Account = assert_initialized(token_account_info)?;
+
+        if *treasury_info.key != payment_option.treasury {
+            return Err(ErrorCode::TreasuryMismatch.into());
+        }
+
+        if token_account.amount < price {
+            return Err(ErrorCode::NotEnoughTokens.into());
+        }
+
+        msg!("Paying with mint {}", payment_option.mint);
+        for (payout_info, amount) in &creator_payouts {
+            spl_token_transfer(TokenTransferParams {
+                source: token_account_info.clone(),
+                destination: (*payout_info).clone(),
+                authority: transfer_authority_info.clone(),
+                authority_signer_seeds: &[],
+                token_program: token_program.clone(),
+                amount: *amount,
+            })?;
+        }
+        spl_token_transfer(TokenTransferParams {
+            source: token_account_info.clone(),
+            destination: treasury_info.clone(),
+            authority: transfer_authority_info.clone(),
+            authority_signer_seeds: &[],
+            token_program: token_program.clone(),
+            amount: wallet_amount,
+        })?;
+    } else if let Some(mint) = nft_vault.token_mint {
+        let token_account_info = &payment_accounts[0];
+        let transfer_authority_info = &payment_accounts[1];
+        let token_account: Account = assert_initialized(token_account_info)?;
+
+        assert_owned_by(token_account_info, &spl_token::id())?;
+
+        if token_account.mint != mint {
+            return Err(ErrorCode::MintMismatch.into());
+        }
+
+        if token_account.amount < price {
+            return Err(ErrorCode::NotEnoughTokens.into());
+        }
+
+        for (payout_info, amount) in &creator_payouts {
+            spl_token_transfer(TokenTransferParams {
+                source: token_account_info.clone(),
+                destination: (*payout_info).clone(),
+                authority: transfer_authority_info.clone(),
+                authority_signer_seeds: &[],
+                token_program: token_program.clone(),
+                amount: *amount,
+            })?;
+        }
+        spl_token_transfer(TokenTransferParams {
+            source: token_account_info.clone(),
+            destination: wallet.clone(),
+            authority: transfer_authority_info.clone(),
+            authority_signer_seeds: &[],
+            token_program: token_program.clone(),
+            amount: wallet_amount,
+        })?;
+    } else {
+        if payer.lamports() < price {
+            return Err(ErrorCode::NotEnoughSOL.into());
+        }
+
+        for (payout_info, amount) in &creator_payouts {
+            invoke(
+                &system_instruction::transfer(payer.key, payout_info.key, *amount),
+                &[
+                    payer.clone(),
+                    (*payout_info).clone(),
+                    system_program.clone(),
+                ],
+            )?;
+        }
+
+        invoke(
+            &system_instruction::transfer(payer.key, wallet.key, wallet_amount),
+            &[payer.clone(), wallet.clone(), system_program.clone()],
+        )?;
+    }
+
+    Ok((unit_price, payment_mint))
+}
+
+pub struct MintItemParams<'a: 'b, 'b> {
+    pub program_id: &'b Pubkey,
+    pub config: &'b Config,
+    pub config_info: AccountInfo<'a>,
+    pub config_pages: &'b [AccountInfo<'a>],
+    pub print_accounts: &'b [AccountInfo<'a>],
+    pub nft_vault: &'b mut NftVault,
+    pub nft_vault_info: AccountInfo<'a>,
+    pub metadata: AccountInfo<'a>,
+    pub mint: AccountInfo<'a>,
+    pub mint_authority: AccountInfo<'a>,
+    pub update_authority: AccountInfo<'a>,
+    pub master_edition: AccountInfo<'a>,
+    pub payer: AccountInfo<'a>,
+    pub token_metadata_program: AccountInfo<'a>,
+    pub token_program: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+    pub rent: AccountInfo<'a>,
+    pub recent_slothashes: AccountInfo<'a>,
+    pub instruction_sysvar: Option<AccountInfo<'a>>,
+    pub edition_marker: Option<AccountInfo<'a>>,
+    pub receipt: Option<AccountInfo<'a>>,
+    pub price: u64,
+    pub payment_mint: Option<Pubkey>,
+    pub unix_timestamp: i64,
+}
+
+/// Takes the next config line (or the hidden settings placeholder), counts it as
+/// redeemed, records it in a MintReceipt when given one and creates its metadata and
+/// master edition.
+pub fn mint_item(params: MintItemParams<'_, '_>) -> ProgramResult {
+    let MintItemParams {
+        program_id,
+        config,
+        config_info,
+        config_pages,
+        print_accounts,
+        nft_vault,
+        nft_vault_info,
+        metadata,
+        mint,
+        mint_authority,
+        update_authority,
+        master_edition,
+        payer,
+        token_metadata_program,
+        token_program,
+        system_program,
+        rent,
+        recent_slothashes,
+        instruction_sysvar,
+        edition_marker,
+        receipt,
+        price,
+        payment_mint,
+        unix_timestamp,
+    } = params;
+
+    if nft_vault.version != NFT_VAULT_VERSION || config.version != CONFIG_VERSION {
+        return Err(ErrorCode::AccountNeedsMigration.into());
+    }
+
+    if nft_vault.data.require_frozen_config {
+        if !config.frozen {
+            return Err(ErrorCode::ConfigNotFrozen.into());
+        }
+        if nft_vault.data.items_available > config.freeze_progress as u64 {
+            return Err(ErrorCode::ItemsAvailableExceedFrozenLines.into());
+        }
+    }
+
+    // Prints have no config line, their receipt records the edition number instead
+    let print_edition = match nft_vault.data.print_master_mint {
+        Some(master_mint) => {
+            let source_edition = match print_accounts.get(1) {
+                Some(val) => val,
+                None => return Err(ErrorCode::PrintAccountsMissing.into()),
+            };
+            if *source_edition.key != find_master_edition_address(&master_mint) {
+                return Err(ErrorCode::DerivedKeyInvalid.into());
+            }
+            assert_owned_by(source_edition, &spl_token_metadata::id())?;
+            let edition = get_master_edition(source_edition)?
+                .supply()
+                .checked_add(1)
+                .ok_or(ErrorCode::NumericalOverflowError)?;
+            msg!("Printing edition {}", edition);
+            Some((master_mint, edition))
+        }
+        None => None,
+    };
+
+    let (config_index, name, uri) = match (print_edition, &nft_vault.data.hidden_settings) {
+        (Some(_), _) => (0, String::new(), String::new()),
+        (None, Some(hidden_settings)) => {
+            // Hidden items are numbered in mint order, which is also the config
+            // line index they receive on reveal.
+            let number = nft_vault
+                .items_redeemed
+                .checked_add(1)
+                .ok_or(ErrorCode::NumericalOverflowError)?;
+            (
+                nft_vault.items_redeemed as usize,
+                format!("{} #{}", hidden_settings.name, number),
+                hidden_settings.uri.clone(),
+            )
+        }
+        (None, None) if !draws_random_lines(nft_vault, config) => {
+            let config_index = nft_vault.items_redeemed as usize;
+            let segment =
+                get_config_segment(&config_info, config, config_pages, program_id, config_index)?;
+            let config_line = get_config_line(&segment, config_index)?;
+            (config_index, config_line.name, config_line.uri)
+        }
+        (None, None) => {
+            match &instruction_sysvar {
+                Some(val) => assert_draw_unobserved(program_id, val)?,
+                None => return Err(ErrorCode::DrawAccountMissing.into()),
+            }
+            let seed = {
+                // Most recent slot hash, skipping the vec length and its slot number
+                let recent_slothashes_data = recent_slothashes.data.borrow();
+                let entropy = keccak::hashv(&[
+                    &recent_slothashes_data[16..48],
+                    &Clock::get()?.slot.to_le_bytes(),
+                    nft_vault_info.key.as_ref(),
+                    payer.key.as_ref(),
+                    &nft_vault.items_redeemed.to_le_bytes(),
+                ]);
+                u64::from_le_bytes(*array_ref![entropy.0, 0, 8])
+            };
+            // Lines are drawn at random over every line of the vault, pages included
+            let segments = get_config_segments(
+                &config_info,
+                config,
+                config_pages,
+                program_id,
+                nft_vault.data.items_available as usize,
+            )?;
+            let (segment, config_index) =
+                take_random_config_index(&segments, nft_vault.data.items_available, seed)?;
+            msg!("Minting config line {}", config_index);
+
+            let config_line = get_config_line(segment, config_index)?;
+            (config_index, config_line.name, config_line.uri)
+        }
+    };
+
+    let index = nft_vault.items_redeemed;
+    let index_bytes = index.to_le_bytes();
+    nft_vault.items_redeemed = nft_vault
+        .items_redeemed
+        .checked_add(1)
+        .ok_or(ErrorCode::NumericalOverflowError)?;
+
+    if let Some(receipt) = receipt {
+        let (receipt_key, receipt_bump) =
+            find_mint_receipt_address(program_id, nft_vault_info.key, index);
+        if receipt_key != *receipt.key {
+            return Err(ErrorCode::DerivedKeyInvalid.into());
+        }
+        create_or_allocate_account_raw(
+            *program_id,
+            &receipt,
+            &rent,
+            &system_program,
+            &payer,
+            MINT_RECEIPT_SIZE,
+            &[
+                PREFIX.as_bytes(),
+                nft_vault_info.key.as_ref(),
+                RECEIPT.as_bytes(),
+                &index_bytes,
+                &[receipt_bump],
+            ],
+        )?;
+        MintReceipt {
+            nft_vault: *nft_vault_info.key,
+            index,
+            config_index: match print_edition {
+                Some(_) => None,
+                None => Some(config_index as u64),
+            },
+            edition: print_edition.map(|(_, edition)| edition),
+            mint: *mint.key,
+            payer: *payer.key,
+            price,
+            payment_mint,
+            timestamp: unix_timestamp,
+            bump: receipt_bump,
+        }
+        .try_serialize(&mut &mut receipt.data.borrow_mut()[..])?;
+    }
+
+    let config_key = config_info.key;
+    let authority_seeds = [
+        PREFIX.as_bytes(),
+        config_key.as_ref(),
+        nft_vault.data.uuid.as_bytes(),
+        &[nft_vault.bump],
+    ];
+
+    if let Some((master_mint, edition)) = print_edition {
+        let (master_metadata, source_edition, vault_token_account, edition_marker) = match (
+            print_accounts.first(),
+            print_accounts.get(1),
+            print_accounts.get(2),
+            &edition_marker,
+        ) {
+            (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+            _ => return Err(ErrorCode::PrintAccountsMissing.into()),
+        };
+
+        invoke_signed(
+            &mint_new_edition_from_master_edition_via_token(
+                *token_metadata_program.key,
+                *metadata.key,
+                *master_edition.key,
+                *source_edition.key,
+                *mint.key,
+                *mint_authority.key,
+                *payer.key,
+                *nft_vault_info.key,
+                *vault_token_account.key,
+                nft_vault.authority,
+                *master_metadata.key,
+                master_mint,
+                edition,
+            ),
+            &[
+                metadata.clone(),
+                master_edition.clone(),
+                source_edition.clone(),
+                mint.clone(),
+                edition_marker.clone(),
+                mint_authority.clone(),
+                payer.clone(),
+                nft_vault_info.clone(),
+                vault_token_account.clone(),
+                master_metadata.clone(),
+                token_metadata_program.clone(),
+                token_program.clone(),
+                system_program.clone(),
+                rent.clone(),
+            ],
+            &[&authority_seeds],
+        )?;
+
+        return Ok(());
+    }
+
+    let mut creators: Vec<spl_token_metadata::state::Creator> =
+        vec![spl_token_metadata::state::Creator {
+            address: *nft_vault_info.key,
+            verified: true,
+            share: 0,
+        }];
+
+    for c in &config.data.creators {
+        creators.push(spl_token_metadata::state::Creator {
+            address: c.address,
+            verified: false,
+            share: c.share,
+        });
+    }
+
+    let metadata_infos = vec![
+        metadata.clone(),
+        mint.clone(),
+        mint_authority.clone(),
+        payer.clone(),
+        token_metadata_program.clone(),
+        token_program.clone(),
+        system_program.clone(),
+        rent.clone(),
+        nft_vault_info.clone(),
+    ];
+
+    let master_edition_infos = vec![
+        master_edition.clone(),
+        mint.clone(),
+        mint_authority.clone(),
+        payer.clone(),
+        metadata.clone(),
+        token_metadata_program.clone(),
+        token_program.clone(),
+        system_program.clone(),
+        rent.clone(),
+        nft_vault_info.clone(),
+    ];
+
+    invoke_signed(
+        &create_metadata_accounts(
+            *token_metadata_program.key,
+            *metadata.key,
+            *mint.key,
+            *mint_authority.key,
+            *payer.key,
+            *nft_vault_info.key,
+            name,
+            config.data.symbol.clone(),
+            uri,
+            Some(creators),
+            config.data.seller_fee_basis_points,
+            false,
+            config.data.is_mutable,
+        ),
+        metadata_infos.as_slice(),
+        &[&authority_seeds],
+    )?;
+
+    invoke_signed(
+        &create_master_edition(
+            *token_metadata_program.key,
+            *master_edition.key,
+            *mint.key,
+            *nft_vault_info.key,
+            *mint_authority.key,
+            *metadata.key,
+            *payer.key,
+            Some(config.data.max_supply),
+        ),
+        master_edition_infos.as_slice(),
+        &[&authority_seeds],
+    )?;
+
+    let mut new_update_authority = Some(nft_vault.authority);
+
+    if nft_vault.data.hidden_settings.is_some() {
+        // The nft vault keeps update authority until reveal_nft hands it over
+        new_update_authority = None;
+    } else if !config.data.retain_authority {
+        new_update_authority = Some(*update_authority.key);
+    }
+
+    invoke_signed(
+        &update_metadata_accounts(
+            *token_metadata_program.key,
+            *metadata.key,
+            *nft_vault_info.key,
+            new_update_authority,
+            None,
+            Some(true),
+        ),
+        &[
+            token_metadata_program.clone(),
+            metadata.clone(),
+            nft_vault_info.clone(),
+        ],
+        &[&authority_seeds],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8, data: NftVaultData)]
+pub struct InitializeNftVault<'info> {
+    #[account(init, seeds=[PREFIX.as_bytes(), config.key().as_ref(), data.uuid.as_bytes()], payer=payer, bump=bump, space=8+32+32+1+33+32+64+64+64+200+33+9+33+9+1+273+8+32+1+33+4+MAX_PAYMENT_OPTIONS*(32+8+32)+1+33+33+33+1+33+4+MAX_SALE_PHASES*(8+8+33+9)+8+8+9+33+1)]
+    nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(constraint= wallet.owner == &spl_token::id() || (wallet.data_is_empty() && wallet.lamports() > 0) )]
+    wallet: AccountInfo<'info>,
+    #[account(mut, has_one=authority, constraint = config.version == CONFIG_VERSION)]
+    config: ProgramAccount<'info, Config>,
+    #[account(signer, constraint= authority.data_is_empty() && authority.lamports() > 0)]
+    authority: AccountInfo<'info>,
+    #[account(mut, signer)]
+    payer: AccountInfo<'info>,
+    #[account(address = system_program::ID)]
+    system_program: AccountInfo<'info>,
+    rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(data: ConfigData, compact: Option<CompactConfig>)]
+pub struct InitializeConfig<'info> {
+    #[account(mut, constraint= config.to_account_info().owner == program_id && config.to_account_info().data_len() >= CONFIG_ARRAY_START+get_config_extension_len(&compact)+4+(data.max_number_of_lines as usize)*get_config_line_size(&compact) + 4 + (2 * get_config_bitmask_len(data.max_number_of_lines)))]
+    config: AccountInfo<'info>,
+    #[account(constraint= authority.data_is_empty() && authority.lamports() > 0 )]
+    authority: AccountInfo<'info>,
+    #[account(mut, signer)]
+    payer: AccountInfo<'info>,
+    rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct AddConfigLines<'info> {
+    #[account(mut, has_one = authority, constraint = config.version == CONFIG_VERSION)]
+    config: ProgramAccount<'info, Config>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
 }
 
 #[derive(Accounts)]
-#[instruction(bump: u8, data: NftVaultData)]
-pub struct InitializeNftVault<'info> {
-    #[account(init, seeds=[PREFIX.as_bytes(), config.key().as_ref(), data.uuid.as_bytes()], payer=payer, bump=bump, space=8+32+32+33+32+64+64+64+200)]
+#[instruction(max_number_of_lines: u32)]
+pub struct InitializeConfigPage<'info> {
+    #[account(mut, constraint= config_page.to_account_info().owner == program_id && config_page.to_account_info().data_len() >= CONFIG_PAGE_ARRAY_START+4+(max_number_of_lines as usize)*get_config_page_line_size(&config) + 4 + (2 * get_config_bitmask_len(max_number_of_lines)))]
+    config_page: AccountInfo<'info>,
+    #[account(mut, has_one = authority, constraint = config.version == CONFIG_VERSION)]
+    config: ProgramAccount<'info, Config>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MintNFT<'info> {
+    #[account(mut, constraint = config.version == CONFIG_VERSION)]
+    config: ProgramAccount<'info, Config>,
+    #[account(
+        mut,
+        has_one = config,
+        has_one = wallet,
+        seeds = [PREFIX.as_bytes(), config.key().as_ref(), nft_vault.data.uuid.as_bytes()],
+        bump = nft_vault.bump,
+        constraint = nft_vault.version == NFT_VAULT_VERSION
+    )]
+    nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(mut, signer)]
+    payer: AccountInfo<'info>,
+    #[account(mut)]
+    wallet: AccountInfo<'info>,
+ 
+    #[account(mut)]
+    metadata: AccountInfo<'info>,
+    #[account(mut)]
+    mint: AccountInfo<'info>,
+    #[account(signer)]
+    mint_authority: AccountInfo<'info>,
+    #[account(signer)]
+    update_authority: AccountInfo<'info>,
+    #[account(mut)]
+    master_edition: AccountInfo<'info>,
+    #[account(address = spl_token_metadata::id())]
+    token_metadata_program: AccountInfo<'info>,
+    #[account(address = spl_token::id())]
+    token_program: AccountInfo<'info>,
+    #[account(address = system_program::ID)]
+    system_program: AccountInfo<'info>,
+    rent: Sysvar<'info, Rent>,
+    clock: Sysvar<'info, Clock>,
+    #[account(address = sysvar::slot_hashes::id())]
+    recent_slothashes: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MintNFTBatch<'info> {
+    #[account(mut, constraint = config.version == CONFIG_VERSION)]
+    config: ProgramAccount<'info, Config>,
+    #[account(
+        mut,
+        has_one = config,
+        has_one = wallet,
+        seeds = [PREFIX.as_bytes(), config.key().as_ref(), nft_vault.data.uuid.as_bytes()],
+        bump = nft_vault.bump,
+        constraint = nft_vault.version == NFT_VAULT_VERSION
+    )]
+    nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(mut, signer)]
+    payer: AccountInfo<'info>,
+    #[account(mut)]
+    wallet: AccountInfo<'info>,
+    #[account(signer)]
+    mint_authority: AccountInfo<'info>,
+    #[account(signer)]
+    update_authority: AccountInfo<'info>,
+    #[account(address = spl_token_metadata::id())]
+    token_metadata_program: AccountInfo<'info>,
+    #[account(address = spl_token::id())]
+    token_program: AccountInfo<'info>,
+    #[account(address = system_program::ID)]
+    system_program: AccountInfo<'info>,
+    rent: Sysvar<'info, Rent>,
+    clock: Sysvar<'info, Clock>,
+    #[account(address = sysvar::slot_hashes::id())]
+    recent_slothashes: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct BurnToMint<'info> {
+    #[account(mut, constraint = config.version == CONFIG_VERSION)]
+    config: ProgramAccount<'info, Config>,
+    #[account(
+        mut,
+        has_one = config,
+        seeds = [PREFIX.as_bytes(), config.key().as_ref(), nft_vault.data.uuid.as_bytes()],
+        bump = nft_vault.bump,
+        constraint = nft_vault.version == NFT_VAULT_VERSION
+    )]
+    nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(mut, signer)]
+    payer: AccountInfo<'info>,
+    #[account(mut)]
+    burn_token_account: AccountInfo<'info>,
+    #[account(mut)]
+    burn_mint: AccountInfo<'info>,
+    burn_metadata: AccountInfo<'info>,
+    burn_edition: AccountInfo<'info>,
+    #[account(mut)]
+    metadata: AccountInfo<'info>,
+    #[account(mut)]
+    mint: AccountInfo<'info>,
+    #[account(signer)]
+    mint_authority: AccountInfo<'info>,
+    #[account(signer)]
+    update_authority: AccountInfo<'info>,
+    #[account(mut)]
+    master_edition: AccountInfo<'info>,
+    #[account(address = spl_token_metadata::id())]
+    token_metadata_program: AccountInfo<'info>,
+    #[account(address = spl_token::id())]
+    token_program: AccountInfo<'info>,
+    #[account(address = system_program::ID)]
+    system_program: AccountInfo<'info>,
+    rent: Sysvar<'info, Rent>,
+    clock: Sysvar<'info, Clock>,
+    #[account(address = sysvar::slot_hashes::id())]
+    recent_slothashes: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TeamMint<'info> {
+    #[account(mut, constraint = config.version == CONFIG_VERSION)]
+    config: ProgramAccount<'info, Config>,
+    #[account(
+        mut,
+        has_one = config,
+        has_one = authority,
+        seeds = [PREFIX.as_bytes(), config.key().as_ref(), nft_vault.data.uuid.as_bytes()],
+        bump = nft_vault.bump,
+        constraint = nft_vault.version == NFT_VAULT_VERSION
+    )]
+    nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+    #[account(mut, signer)]
+    payer: AccountInfo<'info>,
+    #[account(mut)]
+    metadata: AccountInfo<'info>,
+    #[account(mut)]
+    mint: AccountInfo<'info>,
+    #[account(signer)]
+    mint_authority: AccountInfo<'info>,
+    #[account(signer)]
+    update_authority: AccountInfo<'info>,
+    #[account(mut)]
+    master_edition: AccountInfo<'info>,
+    #[account(address = spl_token_metadata::id())]
+    token_metadata_program: AccountInfo<'info>,
+    #[account(address = spl_token::id())]
+    token_program: AccountInfo<'info>,
+    #[account(address = system_program::ID)]
+    system_program: AccountInfo<'info>,
+    rent: Sysvar<'info, Rent>,
+    clock: Sysvar<'info, Clock>,
+    #[account(address = sysvar::slot_hashes::id())]
+    recent_slothashes: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateNftVault<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [PREFIX.as_bytes(), nft_vault.config.key().as_ref(), nft_vault.data.uuid.as_bytes()],
+        bump = nft_vault.bump,
+        constraint = nft_vault.version == NFT_VAULT_VERSION
+    )]
+    nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8, entry_start: i64, entry_end: i64, winner_count: u32, max_tickets: u32)]
+pub struct InitializeRaffle<'info> {
+    #[account(init, seeds=[PREFIX.as_bytes(), nft_vault.key().as_ref(), RAFFLE.as_bytes()], payer=payer, bump=bump, space=RAFFLE_SIZE+(max_tickets as usize)/8+1)]
+    raffle: ProgramAccount<'info, Raffle>,
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [PREFIX.as_bytes(), nft_vault.config.key().as_ref(), nft_vault.data.uuid.as_bytes()],
+        bump = nft_vault.bump,
+        constraint = nft_vault.version == NFT_VAULT_VERSION
+    )]
+    nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+    #[account(mut, signer)]
+    payer: AccountInfo<'info>,
+    #[account(address = system_program::ID)]
+    system_program: AccountInfo<'info>,
+    rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct BuyRaffleTicket<'info> {
+    #[account(
+        mut,
+        has_one = nft_vault,
+        seeds = [PREFIX.as_bytes(), nft_vault.key().as_ref(), RAFFLE.as_bytes()],
+        bump = raffle.bump
+    )]
+    raffle: ProgramAccount<'info, Raffle>,
+    #[account(
+        seeds = [PREFIX.as_bytes(), nft_vault.config.key().as_ref(), nft_vault.data.uuid.as_bytes()],
+        bump = nft_vault.bump,
+        constraint = nft_vault.version == NFT_VAULT_VERSION
+    )]
+    nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(mut)]
+    ticket: AccountInfo<'info>,
+    #[account(mut, signer)]
+    payer: AccountInfo<'info>,
+    #[account(address = spl_token::id())]
+    token_program: AccountInfo<'info>,
+    #[account(address = system_program::ID)]
+    system_program: AccountInfo<'info>,
+    rent: Sysvar<'info, Rent>,
+    clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct DrawRaffle<'info> {
+    #[account(
+        mut,
+        seeds = [PREFIX.as_bytes(), raffle.nft_vault.as_ref(), RAFFLE.as_bytes()],
+        bump = raffle.bump
+    )]
+    raffle: ProgramAccount<'info, Raffle>,
+    clock: Sysvar<'info, Clock>,
+    #[account(address = sysvar::slot_hashes::id())]
+    recent_slothashes: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RefundRaffleTicket<'info> {
+    #[account(
+        mut,
+        has_one = nft_vault,
+        seeds = [PREFIX.as_bytes(), raffle.nft_vault.as_ref(), RAFFLE.as_bytes()],
+        bump = raffle.bump
+    )]
+    raffle: ProgramAccount<'info, Raffle>,
+    #[account(constraint = nft_vault.version == NFT_VAULT_VERSION)]
+    nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(mut)]
+    ticket: AccountInfo<'info>,
+    #[account(mut)]
+    buyer: AccountInfo<'info>,
+    #[account(address = spl_token::id())]
+    token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseRaffle<'info> {
+    #[account(
+        mut,
+        has_one = nft_vault,
+        seeds = [PREFIX.as_bytes(), raffle.nft_vault.as_ref(), RAFFLE.as_bytes()],
+        bump = raffle.bump,
+        close = recipient
+    )]
+    raffle: ProgramAccount<'info, Raffle>,
+    #[account(
+        mut,
+        has_one = authority,
+        has_one = wallet,
+        constraint = nft_vault.version == NFT_VAULT_VERSION
+    )]
+    nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+    #[account(mut)]
+    wallet: AccountInfo<'info>,
+    #[account(mut)]
+    recipient: AccountInfo<'info>,
+    #[account(address = spl_token::id())]
+    token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPaymentOption<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [PREFIX.as_bytes(), nft_vault.config.key().as_ref(), nft_vault.data.uuid.as_bytes()],
+        bump = nft_vault.bump,
+        constraint = nft_vault.version == NFT_VAULT_VERSION
+    )]
+    nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+    mint: AccountInfo<'info>,
+    treasury: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateItemsAvailable<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+        has_one = config,
+        seeds = [PREFIX.as_bytes(), config.key().as_ref(), nft_vault.data.uuid.as_bytes()],
+        bump = nft_vault.bump,
+        constraint = nft_vault.version == NFT_VAULT_VERSION
+    )]
+    nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+    #[account(constraint = config.version == CONFIG_VERSION)]
+    config: ProgramAccount<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateWallet<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [PREFIX.as_bytes(), nft_vault.config.key().as_ref(), nft_vault.data.uuid.as_bytes()],
+        bump = nft_vault.bump,
+        constraint = nft_vault.version == NFT_VAULT_VERSION
+    )]
     nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
     #[account(constraint= wallet.owner == &spl_token::id() || (wallet.data_is_empty() && wallet.lamports() > 0) )]
     wallet: AccountInfo<'info>,
-    #[account(has_one=authority)]
-    config: ProgramAccount<'info, Config>,
-    #[account(signer, constraint= authority.data_is_empty() && authority.lamports() > 0)]
-    authority: AccountInfo<'info>,
-    #[account(mut, signer)]
-    payer: AccountInfo<'info>,
-    #[account(address = system_program::ID)]
-    system_program: AccountInfo<'info>,
-    rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-#[instruction(data: ConfigData)]
-pub struct InitializeConfig<'info> {
-    #[account(mut, constraint= config.to_account_info().owner == program_id && config.to_account_info().data_len() >= CONFIG_ARRAY_START+4+(data.max_number_of_lines as usize)*CONFIG_LINE_SIZE + 4 + (data.max_number_of_lines.checked_div(8).ok_or(ErrorCode::NumericalOverflowError)? as usize))]
-    config: AccountInfo<'info>,
-    #[account(constraint= authority.data_is_empty() && authority.lamports() > 0 )]
-    authority: AccountInfo<'info>,
-    #[account(mut, signer)]
-    payer: AccountInfo<'info>,
-    rent: Sysvar<'info, Rent>,
+pub struct AcceptAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [PREFIX.as_bytes(), nft_vault.config.key().as_ref(), nft_vault.data.uuid.as_bytes()],
+        bump = nft_vault.bump,
+        constraint = nft_vault.version == NFT_VAULT_VERSION
+    )]
+    nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(signer)]
+    new_authority: AccountInfo<'info>,
 }
 
 #[derive(Accounts)]
-pub struct AddConfigLines<'info> {
-    #[account(mut, has_one = authority)]
+pub struct RevealConfig<'info> {
+    #[account(constraint = config.version == CONFIG_VERSION)]
     config: ProgramAccount<'info, Config>,
+    #[account(
+        mut,
+        has_one = authority,
+        has_one = config,
+        seeds = [PREFIX.as_bytes(), config.key().as_ref(), nft_vault.data.uuid.as_bytes()],
+        bump = nft_vault.bump,
+        constraint = nft_vault.version == NFT_VAULT_VERSION
+    )]
+    nft_vault: ProgramAccount<'info, NftVault>,
     #[account(signer)]
     authority: AccountInfo<'info>,
 }
 
 #[derive(Accounts)]
-pub struct MintNFT<'info> {
+pub struct RevealNft<'info> {
+    #[account(constraint = config.version == CONFIG_VERSION)]
     config: ProgramAccount<'info, Config>,
     #[account(
-        mut,
+        has_one = authority,
         has_one = config,
-        has_one = wallet,
         seeds = [PREFIX.as_bytes(), config.key().as_ref(), nft_vault.data.uuid.as_bytes()],
         bump = nft_vault.bump,
+        constraint = nft_vault.version == NFT_VAULT_VERSION
     )]
     nft_vault: ProgramAccount<'info, NftVault>,
-    #[account(mut, signer)]
-    payer: AccountInfo<'info>,
-    #[account(mut)]
-    wallet: AccountInfo<'info>,
- 
-    #[account(mut)]
-    metadata: AccountInfo<'info>,
-    #[account(mut)]
-    mint: AccountInfo<'info>,
     #[account(signer)]
-    mint_authority: AccountInfo<'info>,
-    #[account(signer)]
-    update_authority: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
     #[account(mut)]
-    master_edition: AccountInfo<'info>,
+    metadata: AccountInfo<'info>,
     #[account(address = spl_token_metadata::id())]
     token_metadata_program: AccountInfo<'info>,
-    #[account(address = spl_token::id())]
-    token_program: AccountInfo<'info>,
-    #[account(address = system_program::ID)]
-    system_program: AccountInfo<'info>,
-    rent: Sysvar<'info, Rent>,
-    clock: Sysvar<'info, Clock>,
 }
 
 #[derive(Accounts)]
-pub struct UpdateNftVault<'info> {
+pub struct CloseNftVault<'info> {
     #[account(
         mut,
         has_one = authority,
-        seeds = [PREFIX.as_bytes(), nft_vault.config.key().as_ref(), nft_vault.data.uuid.as_bytes()],
-        bump = nft_vault.bump
+        has_one = config,
+        seeds = [PREFIX.as_bytes(), config.key().as_ref(), nft_vault.data.uuid.as_bytes()],
+        bump = nft_vault.bump,
+        close = recipient,
+        constraint = nft_vault.version == NFT_VAULT_VERSION
     )]
     nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(mut, constraint = config.version == CONFIG_VERSION)]
+    config: ProgramAccount<'info, Config>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+    #[account(mut)]
+    recipient: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FreezeConfig<'info> {
+    #[account(mut, has_one = authority, constraint = config.version == CONFIG_VERSION)]
+    config: ProgramAccount<'info, Config>,
+    #[account(has_one = config, constraint = nft_vault.version == NFT_VAULT_VERSION)]
+    nft_vault: ProgramAccount<'info, NftVault>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseConfig<'info> {
+    #[account(mut, has_one = authority, close = recipient, constraint = config.version == CONFIG_VERSION)]
+    config: ProgramAccount<'info, Config>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+    #[account(mut)]
+    recipient: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseConfigPage<'info> {
+    #[account(mut)]
+    config_page: AccountInfo<'info>,
+    #[account(mut, has_one = authority, constraint = config.version == CONFIG_VERSION)]
+    config: ProgramAccount<'info, Config>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+    #[account(mut)]
+    recipient: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateConfig<'info> {
+    #[account(mut, constraint= config.owner == program_id)]
+    config: AccountInfo<'info>,
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateNftVault<'info> {
+    #[account(mut, constraint= nft_vault.owner == program_id)]
+    nft_vault: AccountInfo<'info>,
+    #[account(mut, constraint = config.version == CONFIG_VERSION)]
+    config: ProgramAccount<'info, Config>,
     #[account(signer)]
     authority: AccountInfo<'info>,
 }
 
+/// Current account layouts. Accounts written before versioning are v1 and read a lower
+/// version: nft vaults have their token_mint option tag where `version` now sits, configs
+/// have a never written zero byte after their header. See migrate_config and
+/// migrate_nft_vault.
+pub const NFT_VAULT_VERSION: u8 = 2;
+pub const CONFIG_VERSION: u8 = 2;
+/// Offset of `NftVault::version`, after the discriminator, authority and wallet.
+pub const NFT_VAULT_VERSION_OFFSET: usize = 8 + 32 + 32;
+/// Space InitializeNftVault allocated before versioning.
+pub const NFT_VAULT_V1_SIZE: usize = 8 + 32 + 32 + 33 + 32 + 64 + 64 + 64 + 200;
+
+/// NftVault as written before versioning, only read by migrate_nft_vault.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct NftVaultV1 {
+    pub authority: Pubkey,
+    pub wallet: Pubkey,
+    pub token_mint: Option<Pubkey>,
+    pub config: Pubkey,
+    pub data: NftVaultDataV1,
+    pub items_redeemed: u64,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct NftVaultDataV1 {
+    pub uuid: String,
+    pub price: u64,
+    pub items_available: u64,
+    pub go_live_date: Option<i64>,
+}
+
+/// Config as written before versioning, only read by migrate_config.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct ConfigV1 {
+    pub authority: Pubkey,
+    pub data: ConfigData,
+}
+
+/// Migrated vaults keep the size v1 allocated, which the settings added since may
+/// outgrow. Rejects an update after which `nft_vault` no longer fits its account.
+pub fn assert_nft_vault_fits(nft_vault: &NftVault, data_len: usize) -> ProgramResult {
+    let len = nft_vault
+        .try_to_vec()
+        .map_err(|_| ErrorCode::NftVaultAccountTooSmall)?
+        .len();
+    if 8 + len > data_len {
+        return Err(ErrorCode::NftVaultAccountTooSmall.into());
+    }
+    Ok(())
+}
+
+/// Rewrites the v1 nft vault in `nft_vault_info`, which must belong to `config` and
+/// `authority`, in place into the current layout.
+pub fn migrate_nft_vault_account(
+    nft_vault_info: &AccountInfo,
+    config: &Pubkey,
+    authority: &Pubkey,
+) -> ProgramResult {
+    let mut data = nft_vault_info.data.borrow_mut();
+    if data.len() <= NFT_VAULT_VERSION_OFFSET || data[..8] != NftVault::discriminator() {
+        return Err(ErrorCode::AccountNotMigratable.into());
+    }
+
+    // v1 has the token_mint option tag, 0 or 1, where the version now sits
+    if data[NFT_VAULT_VERSION_OFFSET] >= NFT_VAULT_VERSION {
+        return Err(ErrorCode::AccountAlreadyMigrated.into());
+    }
+
+    let v1 = NftVaultV1::deserialize(&mut &data[8..])?;
+    if v1.authority != *authority {
+        return Err(ErrorCode::MigrationAuthorityMismatch.into());
+    }
+    if v1.config != *config {
+        return Err(ErrorCode::MigrationConfigMismatch.into());
+    }
+
+    let nft_vault = migrate_nft_vault_v1(v1);
+    let len = data.len();
+    sol_memset(&mut data[8..], 0, len - 8);
+    let mut writer: &mut [u8] = &mut data[..];
+    nft_vault
+        .try_serialize(&mut writer)
+        .map_err(|_| ErrorCode::AccountNotMigratable)?;
+    msg!(
+        "Nft vault {} migrated to version {}",
+        nft_vault_info.key,
+        NFT_VAULT_VERSION
+    );
+
+    Ok(())
+}
+
+/// Current nft vault for a v1 one. Every later setting starts off, so the vault keeps
+/// selling the way it did.
+pub fn migrate_nft_vault_v1(v1: NftVaultV1) -> NftVault {
+    NftVault {
+        authority: v1.authority,
+        wallet: v1.wallet,
+        version: NFT_VAULT_VERSION,
+        token_mint: v1.token_mint,
+        config: v1.config,
+        data: NftVaultData {
+            uuid: v1.data.uuid,
+            price: v1.data.price,
+            items_available: v1.data.items_available,
+            go_live_date: v1.data.go_live_date,
+            max_per_wallet: None,
+            dutch_auction: None,
+            hidden_settings: None,
+            token_gate_creator: None,
+            split_primary_sale: false,
+            burn_to_mint_creator: None,
+            cosigner: None,
+            require_frozen_config: false,
+            print_master_mint: None,
+            phases: vec![],
+            team_reserve: 0,
+            bot_tax: None,
+            record_receipts: false,
+        },
+        items_redeemed: v1.items_redeemed,
+        bump: v1.bump,
+        allowlist_root: None,
+        end_date: None,
+        paused: false,
+        reveal_progress: 0,
+        reveal_hash: [0u8; 32],
+        revealed: false,
+        payment_options: vec![],
+        raffle: None,
+        team_minted: 0,
+        pending_authority: None,
+    }
+}
+
+/// Current config for a v1 one. v1 had no pages or extension, its lines are all at
+/// CONFIG_ARRAY_START. vault_count is rebuilt as its vaults are migrated.
+pub fn migrate_config_v1(v1: ConfigV1) -> Config {
+    Config {
+        authority: v1.authority,
+        data: v1.data,
+        vault_count: 0,
+        page_count: 0,
+        page_capacity: 0,
+        page_line_count: 0,
+        freeze_progress: 0,
+        frozen: false,
+        version: CONFIG_VERSION,
+        extension_len: 0,
+    }
+}
+
+#[account]
+#[derive(Default)]
+pub struct NftVault {
+    pub authority: Pubkey,
+    pub wallet: Pubkey,
+    /// Layout version, see NFT_VAULT_VERSION.
+    pub version: u8,
+    pub token_mint: Option<Pubkey>,
+    pub config: Pubkey,
+    pub data: NftVaultData,
+    pub items_redeemed: u64,
+    pub bump: u8,
+    /// Merkle root of keccak(pubkey) leaves allowed to mint before go live.
+    pub allowlist_root: Option<[u8; 32]>,
+    pub end_date: Option<i64>,
+    pub paused: bool,
+    /// Number of config lines hashed so far by reveal_config.
+    pub reveal_progress: u64,
+    pub reveal_hash: [u8; 32],
+    pub revealed: bool,
+    pub payment_options: Vec<PaymentOption>,
+    /// Set once a raffle is created, after which only its winners may mint.
+    pub raffle: Option<Pubkey>,
+    /// Items taken so far from `data.team_reserve`.
+    pub team_minted: u64,
+    /// Proposed by propose_authority, becomes `authority` once it signs accept_authority.
+    pub pending_authority: Option<Pubkey>,
+}
+
+pub const MAX_PAYMENT_OPTIONS: usize = 4;
+
+/// An spl token accepted in addition to the default `price`/`token_mint`. Options have a
+/// fixed price, so vaults with a dutch auction or sale phases cannot have any.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct PaymentOption {
+    pub mint: Pubkey,
+    pub price: u64,
+    pub treasury: Pubkey,
+}
+
+/// Returns the payment option matching the mint of the supplied token account, if any.
+pub fn get_payment_option(
+    payment_options: &[PaymentOption],
+    token_account_info: Option<&AccountInfo>,
+) -> Option<PaymentOption> {
+    let token_account_info = match token_account_info {
+        Some(val) if val.owner == &spl_token::id() => val,
+        _ => return None,
+    };
+    if payment_options.is_empty() {
+        return None;
+    }
+
+    let token_account: Account = assert_initialized(token_account_info).ok()?;
+    payment_options
+        .iter()
+        .find(|o| o.mint == token_account.mint)
+        .cloned()
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct NftVaultData {
+    pub uuid: String,
+    pub price: u64,
+    pub items_available: u64,
+    pub go_live_date: Option<i64>,
+    pub max_per_wallet: Option<u64>,
+    pub dutch_auction: Option<DutchAuction>,
+    pub hidden_settings: Option<HiddenSettings>,
+    /// When set, only holders of an nft verified by this creator may mint.
+    pub token_gate_creator: Option<Pubkey>,
+    /// Pay each config creator its share of the price at mint time, dust goes to the wallet.
+    pub split_primary_sale: bool,
+    /// Holders of an nft verified by this creator may burn it through burn_to_mint.
+    pub burn_to_mint_creator: Option<Pubkey>,
+    /// When set, every mint needs an ed25519 approval from this key, see assert_cosigned.
+    pub cosigner: Option<Pubkey>,
+    /// Refuse to mint until the config is frozen, see freeze_config.
+    pub require_frozen_config: bool,
+    /// Mint prints of this master edition, whose token the nft vault holds, instead of
+    /// items from config lines.
+    pub print_master_mint: Option<Pubkey>,
+    /// Ordered sale schedule. When set, the active phase's price, allowlist and wallet
+    /// cap replace `price`, `go_live_date`, `max_per_wallet` and the vault allowlist.
+    pub phases: Vec<SalePhase>,
+    /// Items only team_mint may take, on top of what the public can mint.
+    pub team_reserve: u64,
+    /// Lamports mint_nft takes from the payer instead of failing on a mint before go live,
+    /// on an empty vault or with the wrong payment mint, see charge_bot_tax. Only vaults
+    /// that charge sol may set it.
+    pub bot_tax: Option<u64>,
+    /// Create a MintReceipt for every item, see mint_item.
+    pub record_receipts: bool,
+}
+
+/// Every item mints as `name #N` with `uri` until the config lines are revealed.
+/// `hash` commits to the config lines, see reveal_config.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct HiddenSettings {
+    pub name: String,
+    pub uri: String,
+    pub hash: [u8; 32],
+}
+
+/// Price falls linearly from `start_price` at `start_time` to `floor_price`
+/// `decay_interval` seconds later, and stays at the floor afterwards.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct DutchAuction {
+    pub start_price: u64,
+    pub floor_price: u64,
+    pub start_time: i64,
+    pub decay_interval: i64,
+}
+
+pub fn get_mint_price(
+    data: &NftVaultData,
+    unix_timestamp: i64,
+) -> core::result::Result<u64, ProgramError> {
+    if let Some((_, phase)) = get_active_phase(data, unix_timestamp) {
+        return Ok(phase.price);
+    }
+
+    let auction = match &data.dutch_auction {
+        None => return Ok(data.price),
+        Some(val) => val,
+    };
+
+    if unix_timestamp <= auction.start_time {
+        return Ok(auction.start_price);
+    }
+    let elapsed = unix_timestamp - auction.start_time;
+    if elapsed >= auction.decay_interval {
+        return Ok(auction.floor_price);
+    }
+
+    let price_range = auction
+        .start_price
+        .checked_sub(auction.floor_price)
+        .ok_or(ErrorCode::NumericalOverflowError)?;
+    let decayed = (price_range as u128)
+        .checked_mul(elapsed as u128)
+        .ok_or(ErrorCode::NumericalOverflowError)?
+        .checked_div(auction.decay_interval as u128)
+        .ok_or(ErrorCode::NumericalOverflowError)? as u64;
+
+    Ok(auction.start_price - decayed)
+}
+
+pub const MAX_SALE_PHASES: usize = 4;
+
+/// One step of a sale schedule. A phase lasts until the next one starts, or until the
+/// vault's end date for the last one.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, PartialEq)]
+pub struct SalePhase {
+    pub start_time: i64,
+    pub price: u64,
+    /// When set, only wallets in this allowlist may mint during the phase.
+    pub allowlist_root: Option<[u8; 32]>,
+    /// Mints each wallet may make during the phase.
+    pub max_per_wallet: Option<u64>,
+}
+
+/// The latest phase that has started, with its index.
+pub fn get_active_phase(data: &NftVaultData, unix_timestamp: i64) -> Option<(usize, &SalePhase)> {
+    data.phases
+        .iter()
+        .enumerate()
+        .rev()
+        .find(|(_, phase)| phase.start_time <= unix_timestamp)
+}
+
+/// The per wallet cap to enforce now, and the start time of the phase its counter is kept
+/// under. Start times of started phases never change, unlike their index.
+pub fn get_wallet_mint_limit(
+    data: &NftVaultData,
+    unix_timestamp: i64,
+) -> Option<(u64, Option<i64>)> {
+    if data.phases.is_empty() {
+        return data.max_per_wallet.map(|max| (max, None));
+    }
+    let (_, phase) = get_active_phase(data, unix_timestamp)?;
+    phase
+        .max_per_wallet
+        .map(|max| (max, Some(phase.start_time)))
+}
+
+pub fn assert_valid_phases(data: &NftVaultData) -> ProgramResult {
+    if data.phases.len() > MAX_SALE_PHASES
+        || (!data.phases.is_empty() && data.dutch_auction.is_some())
+        || data
+            .phases
+            .windows(2)
+            .any(|pair| pair[0].start_time >= pair[1].start_time)
+    {
+        return Err(ErrorCode::InvalidSalePhases.into());
+    }
+    Ok(())
+}
+
+pub const COSIGN_MESSAGE_LEN: usize = 32 + 32 + 8 + 8;
+pub const COSIGN_NONCE_SIZE: usize = 8 + 32 + 8 + 1;
+
+/// Marks a cosigner nonce as spent for an nft vault.
+#[account]
+#[derive(Default)]
+pub struct CosignNonce {
+    pub nft_vault: Pubkey,
+    pub nonce: u64,
+    pub bump: u8,
+}
+
+pub const RAFFLE_SIZE: usize = 8 + 32 + 8 + 8 + 4 + 4 + 4 + 4 + 4 + 4 + 8 + 33 + 33 + 1 + 4;
+
+/// Ticket sale for an nft vault. Bit `i` of `winners` is set once ticket `i` has won.
+#[account]
+#[derive(Default)]
+pub struct Raffle {
+    pub nft_vault: Pubkey,
+    pub entry_start: i64,
+    pub entry_end: i64,
+    pub winner_count: u32,
+    pub max_tickets: u32,
+    pub ticket_count: u32,
+    pub winners_drawn: u32,
+    /// Winning tickets redeemed or refunded.
+    pub winners_settled: u32,
+    /// All tickets redeemed or refunded, see close_raffle.
+    pub tickets_settled: u32,
+    /// Slot whose hash seeds the draw, see draw_raffle.
+    pub draw_slot: u64,
+    pub draw_seed: Option<[u8; 32]>,
+    /// Token account owned by the raffle holding spl ticket payments.
+    pub escrow: Option<Pubkey>,
+    pub bump: u8,
+    pub winners: Vec<u8>,
+}
+
+pub const RAFFLE_TICKET_SIZE: usize = 8 + 32 + 32 + 4 + 8 + 1;
+
+#[account]
+#[derive(Default)]
+pub struct RaffleTicket {
+    pub raffle: Pubkey,
+    pub buyer: Pubkey,
+    pub index: u32,
+    pub price: u64,
+    pub bump: u8,
+}
+
+pub fn get_raffle_winner_target(raffle: &Raffle) -> u32 {
+    std::cmp::min(raffle.winner_count, raffle.ticket_count)
+}
+
+pub fn is_raffle_winner(raffle: &Raffle, index: u32) -> bool {
+    raffle.winners[index as usize / 8] & (1u8 << (7 - index % 8)) != 0
+}
+
+/// Rejects closing a raffle before entry has ended, every winner is drawn and every
+/// ticket is redeemed or refunded.
+pub fn assert_raffle_settled(raffle: &Raffle, unix_timestamp: i64) -> ProgramResult {
+    if unix_timestamp < raffle.entry_end {
+        return Err(ErrorCode::RaffleEntryNotEnded.into());
+    }
+    if raffle.winners_drawn < get_raffle_winner_target(raffle) {
+        return Err(ErrorCode::RaffleNotDrawn.into());
+    }
+    if raffle.tickets_settled < raffle.ticket_count {
+        return Err(ErrorCode::RaffleTicketsOutstanding.into());
+    }
+    Ok(())
+}
+
+/// Winning tickets not yet redeemed or refunded. Until the draw is seeded the ticket
+/// count is not final, so every winner the raffle may draw is counted.
+pub fn get_unredeemed_winners(raffle: &Raffle) -> core::result::Result<u32, ProgramError> {
+    let winners = match raffle.draw_seed {
+        Some(_) => get_raffle_winner_target(raffle),
+        None => raffle.winner_count,
+    };
+    let unredeemed = winners
+        .checked_sub(raffle.winners_settled)
+        .ok_or(ErrorCode::NumericalOverflowError)?;
+    Ok(unredeemed)
+}
+
+/// Ticket the next winner is drawn from: the `winners_drawn`-th pick of the draw seed,
+/// probing forward to the next ticket that has not won yet.
+pub fn get_raffle_draw(raffle: &Raffle, seed: &[u8; 32], raffle_key: &Pubkey) -> u32 {
+    let entropy = keccak::hashv(&[
+        seed,
+        raffle_key.as_ref(),
+        &raffle.winners_drawn.to_le_bytes(),
+    ]);
+    let seed = u64::from_le_bytes(*array_ref![entropy.0, 0, 8]);
+
+    let mut index = (seed % raffle.ticket_count as u64) as u32;
+    while is_raffle_winner(raffle, index) {
+        index = (index + 1) % raffle.ticket_count;
+    }
+    index
+}
+
+/// Finds the hash of `slot` in the slot hashes sysvar data: a u64 length, then
+/// (slot u64, hash) entries.
+pub fn find_slot_hash(data: &[u8], slot: u64) -> Option<[u8; 32]> {
+    let len = u64::from_le_bytes(*array_ref![data.get(0..8)?, 0, 8]) as usize;
+    for i in 0..len {
+        let entry = data.get(8 + i * 40..8 + (i + 1) * 40)?;
+        if u64::from_le_bytes(*array_ref![entry, 0, 8]) == slot {
+            return Some(*array_ref![entry, 8, 32]);
+        }
+    }
+    None
+}
+
+pub fn find_raffle_ticket_address(
+    program_id: &Pubkey,
+    raffle: &Pubkey,
+    buyer: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PREFIX.as_bytes(),
+            raffle.as_ref(),
+            TICKET.as_bytes(),
+            buyer.as_ref(),
+        ],
+        program_id,
+    )
+}
+
+pub const MINT_RECEIPT_SIZE: usize = 8 + 32 + 8 + 9 + 9 + 32 + 32 + 8 + 33 + 8 + 1;
+
+/// Records which config line, or for print vaults which edition, the `index`-th mint of an
+/// nft vault received, at the address find_mint_receipt_address derives from the vault and
+/// `index`. Clients derive it from the vault's `items_redeemed`, so a mint that races
+/// another for the same index fails and has to be sent again.
+#[account]
+#[derive(Default)]
+pub struct MintReceipt {
+    pub nft_vault: Pubkey,
+    pub index: u64,
+    pub config_index: Option<u64>,
+    pub edition: Option<u64>,
+    pub mint: Pubkey,
+    pub payer: Pubkey,
+    pub price: u64,
+    pub payment_mint: Option<Pubkey>,
+    pub timestamp: i64,
+    pub bump: u8,
+}
+
+pub const WALLET_MINT_COUNTER_SIZE: usize = 8 + 32 + 32 + 8 + 1;
+
+/// Number of items minted by one wallet from one nft vault, created on first mint.
 #[account]
 #[derive(Default)]
-pub struct NftVault {
-    pub authority: Pubkey,
+pub struct WalletMintCounter {
+    pub nft_vault: Pubkey,
     pub wallet: Pubkey,
-    pub token_mint: Option<Pubkey>,
-    pub config: Pubkey,
-    pub data: NftVaultData,
-    pub items_redeemed: u64,
+    pub count: u64,
     pub bump: u8,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
-pub struct NftVaultData {
-    pub uuid: String,
-    pub price: u64,
-    pub items_available: u64,
-    pub go_live_date: Option<i64>,
-}
-
+// The page counters, freeze state, Config::version and Config::extension_len fit in the
+// room kept for the creator slot the config itself takes, so lines stay where v1 put them.
 pub const CONFIG_ARRAY_START: usize = 32 + 
 4 + 6 + 
 4 + MAX_SYMBOL_LENGTH + 
@@ -530,7 +4257,187 @@ pub const CONFIG_ARRAY_START: usize = 32 +
 pub struct Config {
     pub authority: Pubkey,
     pub data: ConfigData,
-   
+    /// Number of open nft vaults minting from this config.
+    pub vault_count: u32,
+    /// Number of linked ConfigPage accounts.
+    pub page_count: u32,
+    /// Lines the pages can hold, the next page starts at `max_number_of_lines + page_capacity`.
+    pub page_capacity: u32,
+    /// Lines loaded across all pages.
+    pub page_line_count: u32,
+    /// Lines hashed into ConfigExtension::content_hash so far, no lines can be added once
+    /// this is set.
+    pub freeze_progress: u32,
+    pub frozen: bool,
+    /// Layout version, see CONFIG_VERSION.
+    pub version: u8,
+    /// Bytes of ConfigExtension between CONFIG_ARRAY_START and the lines.
+    pub extension_len: u16,
+}
+
+/// Settings only some configs use, stored right after the fixed header so the lines of
+/// other configs do not move for them.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct ConfigExtension {
+    /// Store lines as uri suffixes only, see CompactConfig.
+    pub compact: Option<CompactConfig>,
+    /// Hash of the frozen lines, see freeze_config.
+    pub content_hash: [u8; 32],
+}
+
+/// Where the line count and lines of a config start.
+pub fn get_config_array_start(config: &Config) -> usize {
+    CONFIG_ARRAY_START + config.extension_len as usize
+}
+
+/// Bytes the extension holding `compact` takes.
+pub fn get_config_extension_len(compact: &Option<CompactConfig>) -> usize {
+    ConfigExtension {
+        compact: compact.clone(),
+        content_hash: [0u8; 32],
+    }
+    .try_to_vec()
+    .map(|bytes| bytes.len())
+    .unwrap_or(0)
+}
+
+pub fn get_config_extension(
+    config_info: &AccountInfo,
+    config: &Config,
+) -> core::result::Result<ConfigExtension, ProgramError> {
+    if config.extension_len == 0 {
+        return Ok(ConfigExtension::default());
+    }
+    let data = config_info.data.borrow();
+    let extension =
+        ConfigExtension::try_from_slice(&data[CONFIG_ARRAY_START..get_config_array_start(config)])?;
+    Ok(extension)
+}
+
+/// Writes back an extension read with get_config_extension, which keeps its length.
+pub fn set_config_extension(
+    config_info: &AccountInfo,
+    config: &Config,
+    extension: &ConfigExtension,
+) -> ProgramResult {
+    let bytes = extension.try_to_vec()?;
+    if bytes.len() != config.extension_len as usize {
+        return Err(ErrorCode::ConfigNotFreezable.into());
+    }
+    let mut data = config_info.data.borrow_mut();
+    data[CONFIG_ARRAY_START..get_config_array_start(config)].copy_from_slice(&bytes);
+    Ok(())
+}
+
+/// Bytes one line takes in the config's pages, for the InitializeConfigPage constraint.
+pub fn get_config_page_line_size(config: &ProgramAccount<Config>) -> usize {
+    let compact = get_config_extension(&config.to_account_info(), config)
+        .map(|extension| extension.compact)
+        .unwrap_or(None);
+    get_config_line_size(&compact)
+}
+
+pub const CONFIG_PAGE_ARRAY_START: usize = 8 + 32 + 4 + 4 + 4;
+
+/// Overflow lines for a config, laid out like the config itself from
+/// CONFIG_PAGE_ARRAY_START on: a line count, the lines and both bitmasks.
+#[account]
+#[derive(Default)]
+pub struct ConfigPage {
+    pub config: Pubkey,
+    pub page_index: u32,
+    /// Config line index of this page's first line.
+    pub start_index: u32,
+    pub max_number_of_lines: u32,
+}
+
+/// The account holding a run of config lines: the config itself or one of its pages.
+pub struct ConfigSegment<'a> {
+    pub account: AccountInfo<'a>,
+    pub array_start: usize,
+    pub start_index: usize,
+    pub max_number_of_lines: u32,
+    pub line_size: usize,
+    pub compact: Option<CompactConfig>,
+}
+
+/// Finds where config line `index` lives, searching `pages` for lines past the config.
+pub fn get_config_segment<'a>(
+    config_info: &AccountInfo<'a>,
+    config: &Config,
+    pages: &[AccountInfo<'a>],
+    program_id: &Pubkey,
+    index: usize,
+) -> core::result::Result<ConfigSegment<'a>, ProgramError> {
+    let compact = get_config_extension(config_info, config)?.compact;
+    let line_size = get_config_line_size(&compact);
+    if index < config.data.max_number_of_lines as usize {
+        return Ok(ConfigSegment {
+            account: config_info.clone(),
+            array_start: get_config_array_start(config),
+            start_index: 0,
+            max_number_of_lines: config.data.max_number_of_lines,
+            line_size,
+            compact,
+        });
+    }
+
+    for page_info in pages {
+        if page_info.owner != program_id {
+            continue;
+        }
+        let page = match ConfigPage::try_deserialize(&mut &page_info.data.borrow()[..]) {
+            Ok(val) => val,
+            Err(_) => continue,
+        };
+        let start_index = page.start_index as usize;
+        if page.config == *config_info.key
+            && index >= start_index
+            && index < start_index + page.max_number_of_lines as usize
+        {
+            return Ok(ConfigSegment {
+                account: page_info.clone(),
+                array_start: CONFIG_PAGE_ARRAY_START,
+                start_index,
+                max_number_of_lines: page.max_number_of_lines,
+                line_size,
+                compact,
+            });
+        }
+    }
+
+    Err(ErrorCode::ConfigPageMissing.into())
+}
+
+/// The config and every page holding one of its first `items_available` lines, in order.
+pub fn get_config_segments<'a>(
+    config_info: &AccountInfo<'a>,
+    config: &Config,
+    pages: &[AccountInfo<'a>],
+    program_id: &Pubkey,
+    items_available: usize,
+) -> core::result::Result<Vec<ConfigSegment<'a>>, ProgramError> {
+    let mut segments = vec![];
+    let mut index = 0;
+    while index < items_available {
+        let segment = get_config_segment(config_info, config, pages, program_id, index)?;
+        index = segment.start_index + segment.max_number_of_lines as usize;
+        segments.push(segment);
+    }
+    Ok(segments)
+}
+
+/// Lines the config and all of its pages can hold.
+pub fn get_total_config_capacity(config: &Config) -> usize {
+    config.data.max_number_of_lines as usize + config.page_capacity as usize
+}
+
+/// Lines loaded in the config and all of its pages.
+pub fn get_total_config_count(
+    config: &ProgramAccount<Config>,
+) -> core::result::Result<usize, ProgramError> {
+    let count = get_config_count(&config.to_account_info().data.borrow(), config)?;
+    Ok(count + config.page_line_count as usize)
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
@@ -545,28 +4452,194 @@ pub struct ConfigData {
     pub max_number_of_lines: u32,
 }
 
-pub fn get_config_count(data: &Ref<&mut [u8]>) -> core::result::Result<usize, ProgramError> {
-    return Ok(u32::from_le_bytes(*array_ref![data, CONFIG_ARRAY_START, 4]) as usize);
+/// Lines of a compact config hold only a uri suffix of up to `suffix_length` bytes. Line
+/// `i` mints as `name_template` with `{}` replaced by `i + 1`, and `uri_prefix + suffix`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct CompactConfig {
+    pub uri_prefix: String,
+    pub name_template: String,
+    pub suffix_length: u8,
+}
+
+pub fn get_config_count(
+    data: &Ref<&mut [u8]>,
+    config: &Config,
+) -> core::result::Result<usize, ProgramError> {
+    get_config_count_at(data, get_config_array_start(config))
 }
 
+pub fn get_config_count_at(
+    data: &Ref<&mut [u8]>,
+    array_start: usize,
+) -> core::result::Result<usize, ProgramError> {
+    return Ok(u32::from_le_bytes(*array_ref![data, array_start, 4]) as usize);
+}
+
+pub fn find_mint_receipt_address(
+    program_id: &Pubkey,
+    nft_vault: &Pubkey,
+    index: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PREFIX.as_bytes(),
+            nft_vault.as_ref(),
+            RECEIPT.as_bytes(),
+            &index.to_le_bytes(),
+        ],
+        program_id,
+    )
+}
+
+pub fn get_mint_receipt(a: &AccountInfo) -> core::result::Result<MintReceipt, ProgramError> {
+    let data = a.data.borrow();
+    MintReceipt::try_deserialize(&mut &data[..])
+}
+
+/// Reads config line `index` from the segment holding it, rebuilding compact lines into
+/// their full name and uri.
 pub fn get_config_line(
-    a: &AccountInfo,
+    segment: &ConfigSegment,
     index: usize,
 ) -> core::result::Result<ConfigLine, ProgramError> {
-    let arr = a.data.borrow();
+    let arr = segment.account.data.borrow();
+    let array_start = segment.array_start;
+    let line_size = segment.line_size;
+    let line_index = index - segment.start_index;
 
-    let total = get_config_count(&arr)?;
-    if index > total {
+    let total = get_config_count_at(&arr, array_start)?;
+    if line_index > total {
         return Err(ErrorCode::IndexGreaterThanLength.into());
     }
-    let data_array = &arr[CONFIG_ARRAY_START + 4 + index * (CONFIG_LINE_SIZE)
-        ..CONFIG_ARRAY_START + 4 + (index + 1) * (CONFIG_LINE_SIZE)];
+    let data_array = &arr[array_start + 4 + line_index * (line_size)
+        ..array_start + 4 + (line_index + 1) * (line_size)];
 
-    let config_line: ConfigLine = ConfigLine::try_from_slice(data_array)?;
+    let config_line: ConfigLine = match &segment.compact {
+        Some(compact) => {
+            let suffix = String::try_from_slice(data_array)?;
+            ConfigLine {
+                name: compact
+                    .name_template
+                    .replace("{}", &(index + 1).to_string()),
+                uri: compact.uri_prefix.clone() + suffix.trim_matches(char::from(0)),
+            }
+        }
+        None => ConfigLine::try_from_slice(data_array)?,
+    };
 
     Ok(config_line)
 }
 
+/// Bytes taken by one stored config line.
+pub fn get_config_line_size(compact: &Option<CompactConfig>) -> usize {
+    match compact {
+        Some(compact) => 4 + compact.suffix_length as usize,
+        None => CONFIG_LINE_SIZE,
+    }
+}
+
+/// Bytes needed for one bit per config line, for both the loaded and the used bitmasks.
+pub fn get_config_bitmask_len(max_number_of_lines: u32) -> usize {
+    (max_number_of_lines as usize) / 8 + 1
+}
+
+/// Whether config line `index` of the segment holding it was loaded by add_config_lines.
+pub fn is_config_line_loaded(
+    segment: &ConfigSegment,
+    index: usize,
+) -> core::result::Result<bool, ProgramError> {
+    let position = index
+        .checked_sub(segment.start_index)
+        .ok_or(ErrorCode::IndexGreaterThanLength)?;
+    let byte = segment.array_start
+        + 4
+        + (segment.max_number_of_lines as usize) * segment.line_size
+        + 4
+        + position / 8;
+    let data = segment.account.data.borrow();
+    match data.get(byte) {
+        Some(val) => Ok(val & (1u8 << (7 - position % 8)) != 0),
+        None => Err(ErrorCode::IndexGreaterThanLength.into()),
+    }
+}
+
+/// The used bitmask sits right after the loaded-lines bitmask and marks lines already minted.
+pub fn get_config_used_bitmask_start(segment: &ConfigSegment) -> usize {
+    segment.array_start
+        + 4
+        + (segment.max_number_of_lines as usize) * segment.line_size
+        + 4
+        + get_config_bitmask_len(segment.max_number_of_lines)
+}
+
+/// Where the used bitmask of the segment starts and how many of its lines are among the
+/// first `items_available` config lines.
+pub fn get_config_used_range(
+    segment: &ConfigSegment,
+    items_available: usize,
+) -> core::result::Result<(usize, usize), ProgramError> {
+    let used_start = get_config_used_bitmask_start(segment);
+    let lines = std::cmp::min(
+        items_available.saturating_sub(segment.start_index),
+        segment.max_number_of_lines as usize,
+    );
+    if used_start + get_config_bitmask_len(lines as u32) > segment.account.data_len() {
+        return Err(ErrorCode::IndexGreaterThanLength.into());
+    }
+    Ok((used_start, lines))
+}
+
+/// Picks the `seed % free`-th of the first `items_available` config lines, across the
+/// config and its pages, not yet marked used and marks it. Lines another vault of the
+/// config took are skipped.
+pub fn take_random_config_index<'s, 'a>(
+    segments: &'s [ConfigSegment<'a>],
+    items_available: u64,
+    seed: u64,
+) -> core::result::Result<(&'s ConfigSegment<'a>, usize), ProgramError> {
+    let items_available = items_available as usize;
+
+    let mut free = vec![];
+    for segment in segments {
+        let (used_start, lines) = get_config_used_range(segment, items_available)?;
+        let data = segment.account.data.borrow();
+        let mut segment_free = 0u64;
+        for byte_index in 0..get_config_bitmask_len(lines as u32) {
+            let bits_in_byte = std::cmp::min(8, lines - byte_index * 8);
+            let valid_mask = !(0xffu8.checked_shr(bits_in_byte as u32).unwrap_or(0));
+            segment_free += (!data[used_start + byte_index] & valid_mask).count_ones() as u64;
+        }
+        free.push(segment_free);
+    }
+    let total: u64 = free.iter().sum();
+    if total == 0 {
+        return Err(ErrorCode::NftVaultEmpty.into());
+    }
+
+    let mut target = seed % total;
+    for (segment, segment_free) in segments.iter().zip(free) {
+        if target >= segment_free {
+            target -= segment_free;
+            continue;
+        }
+
+        let (used_start, lines) = get_config_used_range(segment, items_available)?;
+        let mut data = segment.account.data.borrow_mut();
+        for line in 0..lines {
+            let mask = 1u8 << (7 - line % 8);
+            if data[used_start + line / 8] & mask == 0 {
+                if target == 0 {
+                    data[used_start + line / 8] |= mask;
+                    return Ok((segment, segment.start_index + line));
+                }
+                target -= 1;
+            }
+        }
+    }
+
+    Err(ErrorCode::NftVaultEmpty.into())
+}
+
 pub const CONFIG_LINE_SIZE: usize = 4 + MAX_NAME_LENGTH + 4 + MAX_URI_LENGTH;
 #[derive(AnchorSerialize, AnchorDeserialize, Debug)]
 pub struct ConfigLine {
@@ -575,6 +4648,50 @@ pub struct ConfigLine {
 }
 
 
+/// Pairs each config creator with its payout account and its share of `price`. Payout
+/// accounts are the creator wallets for sol payments, or creator owned token accounts of
+/// `payment_mint` otherwise.
+pub fn get_creator_payouts<'a, 'info>(
+    creators: &[Creator],
+    payout_infos: &'a [AccountInfo<'info>],
+    price: u64,
+    payment_mint: Option<Pubkey>,
+) -> core::result::Result<Vec<(&'a AccountInfo<'info>, u64)>, ProgramError> {
+    if payout_infos.len() < creators.len() {
+        return Err(ErrorCode::CreatorPayoutAccountsMissing.into());
+    }
+
+    let mut payouts = vec![];
+    for (creator, payout_info) in creators.iter().zip(payout_infos.iter()) {
+        match payment_mint {
+            None => {
+                if *payout_info.key != creator.address {
+                    return Err(ErrorCode::CreatorPayoutMismatch.into());
+                }
+            }
+            Some(mint) => {
+                assert_owned_by(payout_info, &spl_token::id())?;
+                let payout_account: Account = assert_initialized(payout_info)?;
+                if payout_account.owner != creator.address || payout_account.mint != mint {
+                    return Err(ErrorCode::CreatorPayoutMismatch.into());
+                }
+            }
+        }
+
+        let amount = (price as u128)
+            .checked_mul(creator.share as u128)
+            .ok_or(ErrorCode::NumericalOverflowError)?
+            .checked_div(100)
+            .ok_or(ErrorCode::NumericalOverflowError)? as u64;
+        if amount > 0 {
+            msg!("Paying {} to creator {}", amount, creator.address);
+            payouts.push((payout_info, amount));
+        }
+    }
+
+    Ok(payouts)
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
 pub struct Creator {
     pub address: Pubkey,
@@ -612,4 +4729,952 @@ pub enum ErrorCode {
     NftVaultNotLiveYet,
     #[msg("Number of config lines must be at least number of items available")]
     ConfigLineMismatch,
+    #[msg("Allowlist proof is invalid for this wallet")]
+    InvalidAllowlistProof,
+    #[msg("Derived key invalid")]
+    DerivedKeyInvalid,
+    #[msg("This wallet has reached its mint limit for this nft vault")]
+    WalletMintLimitReached,
+    #[msg("Dutch auction floor must not exceed start price and interval must be positive")]
+    InvalidDutchAuction,
+    #[msg("nft vault sale has ended!")]
+    NftVaultSaleEnded,
+    #[msg("nft vault is paused!")]
+    NftVaultPaused,
+    #[msg("Config is still referenced by an open nft vault")]
+    ConfigStillInUse,
+    #[msg("Hidden settings name or uri is too long")]
+    HiddenSettingsTooLong,
+    #[msg("Hidden settings require a mutable config that retains authority")]
+    HiddenSettingsConfigInvalid,
+    #[msg("nft vault does not use hidden settings")]
+    HiddenSettingsNotSet,
+    #[msg("Config lines do not match the hidden settings hash")]
+    HiddenSettingsHashMismatch,
+    #[msg("nft vault is already revealed")]
+    NftVaultAlreadyRevealed,
+    #[msg("nft vault is not revealed yet")]
+    NftVaultNotRevealedYet,
+    #[msg("Metadata is not an unrevealed item of this nft vault")]
+    HiddenMetadataMismatch,
+    #[msg("Token gate requires the holder token account and its metadata")]
+    TokenGateAccountsMissing,
+    #[msg("Payer does not hold an nft verified by the required creator")]
+    NftNotVerifiedByCreator,
+    #[msg("Payment option needs token account, transfer authority and treasury accounts")]
+    PaymentOptionAccountsMissing,
+    #[msg("Treasury does not match the payment option")]
+    TreasuryMismatch,
+    #[msg("Too many payment options for this nft vault")]
+    TooManyPaymentOptions,
+    #[msg("Payment option not found")]
+    PaymentOptionNotFound,
+    #[msg("Primary sale split requires a payout account for every creator")]
+    CreatorPayoutAccountsMissing,
+    #[msg("Payout account does not belong to the creator")]
+    CreatorPayoutMismatch,
+    #[msg("Batch size must be at least one with the accounts of every item")]
+    InvalidBatchSize,
+    #[msg("Burn to mint is not enabled for this nft vault")]
+    BurnToMintNotEnabled,
+    #[msg("Token burn failed")]
+    TokenBurnFailed,
+    #[msg("Invalid raffle settings")]
+    InvalidRaffle,
+    #[msg("Raffle requires its raffle, ticket and escrow accounts")]
+    RaffleAccountsMissing,
+    #[msg("Raffle account does not match")]
+    RaffleMismatch,
+    #[msg("Raffle entry is closed")]
+    RaffleEntryClosed,
+    #[msg("Raffle entry has not ended yet")]
+    RaffleEntryNotEnded,
+    #[msg("Raffle has sold all its tickets")]
+    RaffleFull,
+    #[msg("Raffle winners are already drawn")]
+    RaffleAlreadyDrawn,
+    #[msg("Raffle winners are not drawn yet")]
+    RaffleNotDrawn,
+    #[msg("Raffle ticket does not belong to this raffle and wallet")]
+    RaffleTicketMismatch,
+    #[msg("Raffle ticket did not win")]
+    NotRaffleWinner,
+    #[msg("Winning raffle tickets can only be refunded once the sale is paused, over or sold out")]
+    RaffleWinnerCannotRefund,
+    #[msg("Raffle winners must mint one at a time")]
+    RaffleRequiresSingleMint,
+    #[msg("Mint requires an ed25519 cosigner instruction right before it")]
+    CosignatureMissing,
+    #[msg("Cosigner signature does not match this vault and payer")]
+    CosignatureInvalid,
+    #[msg("Cosigner approval has expired")]
+    CosignatureExpired,
+    #[msg("Cosigner nonce was already used")]
+    CosignNonceUsed,
+    #[msg("Cosigned vaults must mint one at a time")]
+    CosignerRequiresSingleMint,
+    #[msg("Recipient account does not match the recipient argument")]
+    RecipientMismatch,
+    #[msg("Mint must have no supply when minting to a recipient")]
+    MintAlreadyHasSupply,
+    #[msg("Failed to create the associated token account")]
+    AssociatedTokenAccountCreationFailed,
+    #[msg("Token mint to failed")]
+    TokenMintToFailed,
+    #[msg("Config page is already initialized")]
+    ConfigPageAlreadyInitialized,
+    #[msg("Config page holding this config line is missing")]
+    ConfigPageMissing,
+    #[msg("Config page does not match, only the last page can be closed")]
+    ConfigPageMismatch,
+    #[msg("Config pages must be closed before the config")]
+    ConfigHasPages,
+    #[msg("Compact config prefix, suffix length or name template is invalid")]
+    InvalidCompactConfig,
+    #[msg("Config line uri is longer than the compact suffix length")]
+    ConfigLineTooLong,
+    #[msg("Config is frozen")]
+    ConfigFrozen,
+    #[msg("Every config line up to the vault's items available must be loaded to freeze")]
+    ConfigNotComplete,
+    #[msg("nft vault requires a frozen config")]
+    ConfigNotFrozen,
+    #[msg(
+        "Print mode requires the master metadata, master edition, token account and edition marker"
+    )]
+    PrintAccountsMissing,
+    #[msg("Items available exceed the master edition's remaining supply")]
+    PrintSupplyExceeded,
+    #[msg("Print mode cannot use hidden settings")]
+    PrintModeInvalid,
+    #[msg("Sale phases must be in start order, at most 4, and without a dutch auction")]
+    InvalidSalePhases,
+    #[msg("Sale phases that have started cannot be changed")]
+    SalePhaseStarted,
+    #[msg("Team reserve cannot exceed items available")]
+    TeamReserveTooLarge,
+    #[msg("Team reserve is fully minted")]
+    TeamReserveExhausted,
+    #[msg("Items available cannot drop below items redeemed")]
+    ItemsAvailableBelowRedeemed,
+    #[msg("Items available cannot change once the reveal has started")]
+    RevealAlreadyStarted,
+    #[msg("Signer is not the pending authority of this nft vault")]
+    NotPendingAuthority,
+    #[msg("Account uses an old layout, run migrate first")]
+    AccountNeedsMigration,
+    #[msg("Account is not a v1 nft vault or config")]
+    AccountNotMigratable,
+    #[msg("Account already uses the current layout")]
+    AccountAlreadyMigrated,
+    #[msg("Signer is not the authority of the account to migrate")]
+    MigrationAuthorityMismatch,
+    #[msg("Config is not the config of the nft vault to migrate")]
+    MigrationConfigMismatch,
+    #[msg("Wallet cap requires the wallet mint counter account")]
+    WalletMintCounterMissing,
+    #[msg("Cosigner requires the cosign nonce and the instructions sysvar accounts")]
+    CosignAccountsMissing,
+    #[msg("Recipient mint requires the recipient, its token account and the token program")]
+    RecipientAccountsMissing,
+    #[msg("nft vault records receipts, the receipt account is missing")]
+    ReceiptAccountMissing,
+    #[msg("Token payments require the token account and transfer authority accounts")]
+    PaymentAccountsMissing,
+    #[msg("Payment options cannot be combined with a dutch auction or sale phases")]
+    PaymentOptionPricingConflict,
+    #[msg("Config has no extension to keep the content hash in")]
+    ConfigNotFreezable,
+    #[msg("Items available exceed the frozen config lines")]
+    ItemsAvailableExceedFrozenLines,
+    #[msg("Random line draws require the instructions sysvar account")]
+    DrawAccountMissing,
+    #[msg("Random line draws must be the last top level instruction of the transaction")]
+    DrawObservable,
+    #[msg("Config pages must be writable")]
+    ConfigPageNotWritable,
+    #[msg("Payment options cannot be combined with a raffle")]
+    RafflePaymentOptionConflict,
+    #[msg("Each wallet can buy one raffle ticket")]
+    RaffleTicketAlreadyBought,
+    #[msg("Raffle draw slot has not passed yet")]
+    RaffleDrawSlotNotReached,
+    #[msg("The vault currency cannot change while it has a raffle")]
+    RaffleCurrencyLocked,
+    #[msg("Items available cannot drop below the public mints and unredeemed raffle winners")]
+    ItemsAvailableBelowOwed,
+    #[msg(
+        "Nft vault can only be closed before its first mint, once sold out or after its end date"
+    )]
+    NftVaultSaleInProgress,
+    #[msg("Nft vault has an open raffle, close it first")]
+    NftVaultHasRaffle,
+    #[msg("Bot tax is paid in sol and needs a vault that charges sol")]
+    BotTaxRequiresSolWallet,
+    #[msg("Raffle tickets are sold at the vault price, a dutch auction or sale phases replace it")]
+    RafflePricingConflict,
+    #[msg("Raffle tickets are still waiting to be redeemed or refunded")]
+    RaffleTicketsOutstanding,
+    #[msg("Token account close failed")]
+    TokenCloseFailed,
+    #[msg("Nft vault account is too small for this update, migrated vaults keep their v1 size")]
+    NftVaultAccountTooSmall,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{verify_merkle_proof, ED25519_PROGRAM_ID};
+    use anchor_lang::solana_program::{instruction::Instruction, message::Message};
+
+    fn frozen_config(freeze_progress: u32) -> Config {
+        Config {
+            freeze_progress,
+            frozen: true,
+            version: CONFIG_VERSION,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn reveal_requires_frozen_config() {
+        let config = Config {
+            freeze_progress: 10,
+            ..Config::default()
+        };
+        assert_eq!(
+            assert_reveal_bound(&config, 10),
+            Err(ErrorCode::ConfigNotFrozen.into())
+        );
+    }
+
+    #[test]
+    fn reveal_requires_every_item_frozen() {
+        let config = frozen_config(10);
+        assert_eq!(
+            assert_reveal_bound(&config, 11),
+            Err(ErrorCode::ItemsAvailableExceedFrozenLines.into())
+        );
+        assert_eq!(assert_reveal_bound(&config, 10), Ok(()));
+        assert_eq!(assert_reveal_bound(&config, 4), Ok(()));
+    }
+
+    #[test]
+    fn reveal_hash_commits_to_every_line() {
+        let lines: Vec<Vec<u8>> = (0..3u8).map(|i| vec![i; CONFIG_LINE_SIZE]).collect();
+        let chain = |lines: &[Vec<u8>]| {
+            lines
+                .iter()
+                .fold([0u8; 32], |hash, line| hash_config_line(&hash, line))
+        };
+
+        let h1 = keccak::hashv(&[&[0u8; 32], &lines[0]]).0;
+        let h2 = keccak::hashv(&[&h1, &lines[1]]).0;
+        let h3 = keccak::hashv(&[&h2, &lines[2]]).0;
+        assert_eq!(chain(&lines), h3);
+
+        let mut swapped = lines.clone();
+        swapped.swap(0, 1);
+        assert_ne!(chain(&swapped), h3);
+        assert_ne!(chain(&lines[..2]), h3);
+    }
+
+    #[test]
+    fn raffle_seed_uses_the_committed_slot_only() {
+        let mut data = 2u64.to_le_bytes().to_vec();
+        for (slot, byte) in &[(41u64, 1u8), (40, 2)] {
+            data.extend_from_slice(&slot.to_le_bytes());
+            data.extend_from_slice(&[*byte; 32]);
+        }
+        assert_eq!(find_slot_hash(&data, 40), Some([2u8; 32]));
+        assert_eq!(find_slot_hash(&data, 41), Some([1u8; 32]));
+        assert_eq!(find_slot_hash(&data, 39), None);
+        assert_eq!(find_slot_hash(&data[..50], 40), None);
+    }
+
+    fn minted_vault(items_redeemed: u64, team_minted: u64, team_reserve: u64) -> NftVault {
+        NftVault {
+            items_redeemed,
+            team_minted,
+            data: NftVaultData {
+                items_available: 100,
+                team_reserve,
+                ..NftVaultData::default()
+            },
+            ..NftVault::default()
+        }
+    }
+
+    #[test]
+    fn supply_covers_public_mints_and_unredeemed_winners() {
+        // 30 public and 5 team mints so far, 10 items kept for the team
+        let nft_vault = minted_vault(35, 5, 10);
+        assert_eq!(assert_supply_covers(&nft_vault, 45, 5), Ok(()));
+        assert_eq!(
+            assert_supply_covers(&nft_vault, 44, 5),
+            Err(ErrorCode::ItemsAvailableBelowOwed.into())
+        );
+        assert_eq!(assert_supply_covers(&nft_vault, 40, 0), Ok(()));
+        assert_eq!(
+            assert_supply_covers(&nft_vault, 9, 0),
+            Err(ErrorCode::TeamReserveTooLarge.into())
+        );
+    }
+
+    #[test]
+    fn unredeemed_winners_shrink_as_tickets_settle() {
+        let mut raffle = Raffle {
+            winner_count: 10,
+            ticket_count: 4,
+            ..Raffle::default()
+        };
+        // Tickets can still be sold until the draw is seeded
+        assert_eq!(get_unredeemed_winners(&raffle), Ok(10));
+
+        raffle.draw_seed = Some([7u8; 32]);
+        assert_eq!(get_unredeemed_winners(&raffle), Ok(4));
+
+        raffle.winners_settled = 3;
+        assert_eq!(get_unredeemed_winners(&raffle), Ok(1));
+    }
+
+    /// Lays `v1` out behind `discriminator` in a zeroed account of `len` bytes.
+    fn v1_account<T: AnchorSerialize>(discriminator: [u8; 8], v1: &T, len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        data[..8].copy_from_slice(&discriminator);
+        let bytes = v1.try_to_vec().unwrap();
+        data[8..8 + bytes.len()].copy_from_slice(&bytes);
+        data
+    }
+
+    #[test]
+    fn migrated_nft_vault_keeps_v1_fields() {
+        let v1 = NftVaultV1 {
+            authority: Pubkey::new_unique(),
+            wallet: Pubkey::new_unique(),
+            token_mint: Some(Pubkey::new_unique()),
+            config: Pubkey::new_unique(),
+            data: NftVaultDataV1 {
+                uuid: "abcdef".to_string(),
+                price: 5,
+                items_available: 100,
+                go_live_date: Some(1_000),
+            },
+            items_redeemed: 42,
+            bump: 254,
+        };
+        let mut data = v1_account(NftVault::discriminator(), &v1, NFT_VAULT_V1_SIZE);
+        assert!(data[NFT_VAULT_VERSION_OFFSET] < NFT_VAULT_VERSION);
+
+        let key = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let mut lamports = 0;
+        let nft_vault_info = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        assert_eq!(
+            migrate_nft_vault_account(&nft_vault_info, &Pubkey::new_unique(), &v1.authority),
+            Err(ErrorCode::MigrationConfigMismatch.into())
+        );
+        assert_eq!(
+            migrate_nft_vault_account(&nft_vault_info, &v1.config, &Pubkey::new_unique()),
+            Err(ErrorCode::MigrationAuthorityMismatch.into())
+        );
+        assert_eq!(
+            migrate_nft_vault_account(&nft_vault_info, &v1.config, &v1.authority),
+            Ok(())
+        );
+        // A second pass must not count the vault on its config again
+        assert_eq!(
+            migrate_nft_vault_account(&nft_vault_info, &v1.config, &v1.authority),
+            Err(ErrorCode::AccountAlreadyMigrated.into())
+        );
+
+        let data = nft_vault_info.data.borrow();
+        let nft_vault = NftVault::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(data[NFT_VAULT_VERSION_OFFSET], NFT_VAULT_VERSION);
+        assert_eq!(nft_vault.authority, v1.authority);
+        assert_eq!(nft_vault.wallet, v1.wallet);
+        assert_eq!(nft_vault.token_mint, v1.token_mint);
+        assert_eq!(nft_vault.config, v1.config);
+        assert_eq!(nft_vault.data.uuid, v1.data.uuid);
+        assert_eq!(nft_vault.data.price, 5);
+        assert_eq!(nft_vault.data.items_available, 100);
+        assert_eq!(nft_vault.data.go_live_date, Some(1_000));
+        assert_eq!(nft_vault.items_redeemed, 42);
+        assert_eq!(nft_vault.bump, 254);
+        assert!(nft_vault.raffle.is_none() && !nft_vault.paused);
+    }
+
+    #[test]
+    fn migrated_config_header_fits_before_v1_lines() {
+        let creators = (0..MAX_CREATOR_LIMIT - 1)
+            .map(|_| Creator {
+                address: Pubkey::new_unique(),
+                verified: false,
+                share: 25,
+            })
+            .collect();
+        let v1 = ConfigV1 {
+            authority: Pubkey::new_unique(),
+            data: ConfigData {
+                uuid: "abcdef".to_string(),
+                symbol: "A".repeat(MAX_SYMBOL_LENGTH),
+                seller_fee_basis_points: 500,
+                creators,
+                max_supply: 0,
+                is_mutable: true,
+                retain_authority: true,
+                max_number_of_lines: 10,
+            },
+        };
+        let mut data = v1_account(Config::discriminator(), &v1, CONFIG_ARRAY_START + 4);
+        data[CONFIG_ARRAY_START..].copy_from_slice(&3u32.to_le_bytes());
+
+        // The bytes v1 never wrote read as version 0
+        let current = Config::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(current.version, 0);
+
+        let old = ConfigV1::deserialize(&mut &data[8..]).unwrap();
+        let mut writer: &mut [u8] = &mut data[..CONFIG_ARRAY_START];
+        migrate_config_v1(old).try_serialize(&mut writer).unwrap();
+
+        let config = Config::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.authority, v1.authority);
+        assert_eq!(config.data.symbol, v1.data.symbol);
+        assert_eq!(config.data.creators.len(), MAX_CREATOR_LIMIT - 1);
+        assert_eq!(config.data.max_number_of_lines, 10);
+        assert_eq!((config.vault_count, config.extension_len), (0, 0));
+        assert_eq!(get_config_array_start(&config), CONFIG_ARRAY_START);
+        // The line count v1 wrote is left in place
+        assert_eq!(data[CONFIG_ARRAY_START..], 3u32.to_le_bytes());
+    }
+
+    #[test]
+    fn nft_vault_closes_only_outside_a_sale() {
+        assert_eq!(assert_nft_vault_closable(&minted_vault(0, 0, 0), 0), Ok(()));
+        assert_eq!(
+            assert_nft_vault_closable(&minted_vault(40, 0, 0), 0),
+            Err(ErrorCode::NftVaultSaleInProgress.into())
+        );
+        assert_eq!(
+            assert_nft_vault_closable(&minted_vault(100, 0, 0), 0),
+            Ok(())
+        );
+
+        let mut nft_vault = minted_vault(40, 0, 0);
+        nft_vault.end_date = Some(1_000);
+        assert_eq!(
+            assert_nft_vault_closable(&nft_vault, 999),
+            Err(ErrorCode::NftVaultSaleInProgress.into())
+        );
+        assert_eq!(assert_nft_vault_closable(&nft_vault, 1_000), Ok(()));
+
+        nft_vault.data.hidden_settings = Some(HiddenSettings::default());
+        assert_eq!(
+            assert_nft_vault_closable(&nft_vault, 1_000),
+            Err(ErrorCode::NftVaultNotRevealedYet.into())
+        );
+        nft_vault.revealed = true;
+        assert_eq!(assert_nft_vault_closable(&nft_vault, 1_000), Ok(()));
+
+        let mut raffled = minted_vault(0, 0, 0);
+        raffled.raffle = Some(Pubkey::new_unique());
+        assert_eq!(
+            assert_nft_vault_closable(&raffled, 0),
+            Err(ErrorCode::NftVaultHasRaffle.into())
+        );
+        // close_raffle clears it once every ticket is settled
+        raffled.raffle = None;
+        assert_eq!(assert_nft_vault_closable(&raffled, 0), Ok(()));
+    }
+
+    #[test]
+    fn raffle_closes_once_every_ticket_settles() {
+        let mut raffle = Raffle {
+            entry_end: 1_000,
+            winner_count: 2,
+            ticket_count: 5,
+            winners_drawn: 2,
+            ..Raffle::default()
+        };
+        assert_eq!(
+            assert_raffle_settled(&raffle, 999),
+            Err(ErrorCode::RaffleEntryNotEnded.into())
+        );
+
+        // Both winners redeemed, three losing tickets still to refund
+        raffle.winners_settled = 2;
+        raffle.tickets_settled = 2;
+        assert_eq!(
+            assert_raffle_settled(&raffle, 1_000),
+            Err(ErrorCode::RaffleTicketsOutstanding.into())
+        );
+        raffle.tickets_settled = 5;
+        assert_eq!(assert_raffle_settled(&raffle, 1_000), Ok(()));
+
+        raffle.winners_drawn = 1;
+        assert_eq!(
+            assert_raffle_settled(&raffle, 1_000),
+            Err(ErrorCode::RaffleNotDrawn.into())
+        );
+
+        // Nobody entered
+        let empty = Raffle {
+            entry_end: 1_000,
+            winner_count: 2,
+            ..Raffle::default()
+        };
+        assert_eq!(assert_raffle_settled(&empty, 1_000), Ok(()));
+    }
+
+    #[test]
+    fn migrated_nft_vault_rejects_settings_it_cannot_hold() {
+        let mut nft_vault = migrate_nft_vault_v1(NftVaultV1 {
+            token_mint: Some(Pubkey::new_unique()),
+            data: NftVaultDataV1 {
+                uuid: "abcdef".to_string(),
+                go_live_date: Some(1_000),
+                ..NftVaultDataV1::default()
+            },
+            ..NftVaultV1::default()
+        });
+        assert_eq!(assert_nft_vault_fits(&nft_vault, NFT_VAULT_V1_SIZE), Ok(()));
+
+        nft_vault.payment_options = vec![PaymentOption::default(); MAX_PAYMENT_OPTIONS];
+        assert_eq!(
+            assert_nft_vault_fits(&nft_vault, NFT_VAULT_V1_SIZE),
+            Err(ErrorCode::NftVaultAccountTooSmall.into())
+        );
+        nft_vault.payment_options.truncate(2);
+        assert_eq!(assert_nft_vault_fits(&nft_vault, NFT_VAULT_V1_SIZE), Ok(()));
+    }
+
+    fn hash_pair(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+        if a <= b {
+            keccak::hashv(&[&a, &b]).0
+        } else {
+            keccak::hashv(&[&b, &a]).0
+        }
+    }
+
+    #[test]
+    fn allowlist_proof_must_lead_to_the_root() {
+        let wallets = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let leaves: Vec<[u8; 32]> = wallets
+            .iter()
+            .map(|wallet| keccak::hashv(&[wallet.as_ref()]).0)
+            .collect();
+        let node = hash_pair(leaves[0], leaves[1]);
+        let root = hash_pair(node, leaves[2]);
+
+        assert!(verify_merkle_proof(
+            &[leaves[1], leaves[2]],
+            root,
+            leaves[0]
+        ));
+        assert!(verify_merkle_proof(&[node], root, leaves[2]));
+        assert!(!verify_merkle_proof(
+            &[leaves[0], leaves[2]],
+            root,
+            leaves[0]
+        ));
+        assert!(!verify_merkle_proof(&[], root, leaves[0]));
+
+        let proof = Some(vec![leaves[1], leaves[2]]);
+        assert_eq!(assert_allowlisted(Some(root), &proof, &wallets[0]), Ok(()));
+        assert_eq!(
+            assert_allowlisted(Some(root), &proof, &wallets[1]),
+            Err(ErrorCode::InvalidAllowlistProof.into())
+        );
+        // Without a proof or an allowlist the vault is simply not live yet
+        assert_eq!(
+            assert_allowlisted(Some(root), &None, &wallets[0]),
+            Err(ErrorCode::NftVaultNotLiveYet.into())
+        );
+        assert_eq!(
+            assert_allowlisted(None, &proof, &wallets[0]),
+            Err(ErrorCode::NftVaultNotLiveYet.into())
+        );
+    }
+
+    /// Account over `data` for helpers that only read or write account data.
+    fn data_account<'a>(
+        key: &'a Pubkey,
+        lamports: &'a mut u64,
+        data: &'a mut [u8],
+    ) -> AccountInfo<'a> {
+        AccountInfo::new(key, false, true, lamports, data, key, false, 0)
+    }
+
+    /// Ten one byte lines starting at config line `start_index`.
+    fn used_segment<'a>(account: AccountInfo<'a>, start_index: usize) -> ConfigSegment<'a> {
+        ConfigSegment {
+            account,
+            array_start: 0,
+            start_index,
+            max_number_of_lines: 10,
+            line_size: 1,
+            compact: None,
+        }
+    }
+
+    /// Data of a used_segment whose used bitmask is `used`.
+    fn used_bitmask(used: [u8; 2]) -> Vec<u8> {
+        let mut data = vec![0u8; 4 + 10 + 4 + 2];
+        data.extend_from_slice(&used);
+        data
+    }
+
+    #[test]
+    fn random_index_skips_used_lines_and_lines_past_items_available() {
+        let (key_a, key_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (mut lamports_a, mut lamports_b) = (0, 0);
+        // Every line of the config is used, the page has none used
+        let mut data_a = used_bitmask([0xff, 0b1100_0000]);
+        let mut data_b = used_bitmask([0, 0]);
+        let segments = [
+            used_segment(data_account(&key_a, &mut lamports_a, &mut data_a), 0),
+            used_segment(data_account(&key_b, &mut lamports_b, &mut data_b), 10),
+        ];
+
+        // Only the page's first two lines are among the 12 items
+        let (segment, index) = take_random_config_index(&segments, 12, 3).unwrap();
+        assert_eq!((segment.start_index, index), (10, 11));
+        let (_, index) = take_random_config_index(&segments, 12, 3).unwrap();
+        assert_eq!(index, 10);
+        assert_eq!(
+            take_random_config_index(&segments, 12, 3).err(),
+            Some(ErrorCode::NftVaultEmpty.into())
+        );
+        assert_eq!(segments[1].account.data.borrow()[20], 0b1100_0000);
+    }
+
+    #[test]
+    fn random_index_target_crosses_into_later_segments() {
+        let (key_a, key_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (mut lamports_a, mut lamports_b) = (0, 0);
+        // Lines 0, 2 and 4 are used, leaving 7 free in the config and 2 on the page
+        let mut data_a = used_bitmask([0b1010_1000, 0]);
+        let mut data_b = used_bitmask([0, 0]);
+        let segments = [
+            used_segment(data_account(&key_a, &mut lamports_a, &mut data_a), 0),
+            used_segment(data_account(&key_b, &mut lamports_b, &mut data_b), 10),
+        ];
+
+        let (_, index) = take_random_config_index(&segments, 12, 9 + 7).unwrap();
+        assert_eq!(index, 10);
+        // 8 free lines left, the 7th of them is the config's last line
+        let (_, index) = take_random_config_index(&segments, 12, 6).unwrap();
+        assert_eq!(index, 9);
+        let (_, index) = take_random_config_index(&segments, 12, 0).unwrap();
+        assert_eq!(index, 1);
+    }
+
+    #[test]
+    fn dutch_auction_decays_linearly_to_its_floor() {
+        let data = NftVaultData {
+            price: 1,
+            dutch_auction: Some(DutchAuction {
+                start_price: 1_000,
+                floor_price: 200,
+                start_time: 100,
+                decay_interval: 400,
+            }),
+            ..NftVaultData::default()
+        };
+        assert_eq!(get_mint_price(&data, 0), Ok(1_000));
+        assert_eq!(get_mint_price(&data, 100), Ok(1_000));
+        assert_eq!(get_mint_price(&data, 200), Ok(800));
+        assert_eq!(get_mint_price(&data, 499), Ok(202));
+        assert_eq!(get_mint_price(&data, 500), Ok(200));
+        assert_eq!(get_mint_price(&data, i64::MAX), Ok(200));
+
+        let fixed = NftVaultData {
+            price: 7,
+            ..NftVaultData::default()
+        };
+        assert_eq!(get_mint_price(&fixed, 500), Ok(7));
+    }
+
+    #[test]
+    fn creator_payouts_leave_the_dust_to_the_wallet() {
+        let keys = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let creators: Vec<Creator> = keys
+            .iter()
+            .zip([33u8, 33, 34].iter())
+            .map(|(key, share)| Creator {
+                address: *key,
+                verified: true,
+                share: *share,
+            })
+            .collect();
+        let (mut l0, mut l1, mut l2) = (0, 0, 0);
+        let (mut d0, mut d1, mut d2) = (vec![], vec![], vec![]);
+        let payout_infos = [
+            data_account(&keys[0], &mut l0, &mut d0),
+            data_account(&keys[1], &mut l1, &mut d1),
+            data_account(&keys[2], &mut l2, &mut d2),
+        ];
+
+        let amounts = |payouts: Vec<(&AccountInfo, u64)>| -> Vec<(Pubkey, u64)> {
+            payouts
+                .into_iter()
+                .map(|(info, amount)| (*info.key, amount))
+                .collect()
+        };
+        let payouts = get_creator_payouts(&creators, &payout_infos, 10, None).unwrap();
+        // 1 of the 10 is dust and stays with the wallet
+        assert_eq!(
+            amounts(payouts),
+            vec![(keys[0], 3), (keys[1], 3), (keys[2], 3)]
+        );
+        // Shares rounding to nothing are skipped
+        let payouts = get_creator_payouts(&creators, &payout_infos, 2, None).unwrap();
+        assert!(payouts.is_empty());
+
+        assert_eq!(
+            get_creator_payouts(&creators, &payout_infos[..2], 10, None).err(),
+            Some(ErrorCode::CreatorPayoutAccountsMissing.into())
+        );
+        let swapped = [
+            payout_infos[1].clone(),
+            payout_infos[0].clone(),
+            payout_infos[2].clone(),
+        ];
+        assert_eq!(
+            get_creator_payouts(&creators, &swapped, 10, None).err(),
+            Some(ErrorCode::CreatorPayoutMismatch.into())
+        );
+    }
+
+    /// Data of an ed25519 program instruction checking one signature by `signer` over
+    /// `message`, with every offset pointing into `instruction_index`.
+    fn ed25519_data(signer: &Pubkey, message: &[u8], instruction_index: u16) -> Vec<u8> {
+        let public_key_offset = 2 + 14;
+        let signature_offset = public_key_offset + 32;
+        let message_offset = signature_offset + 64;
+        let mut data = vec![1u8, 0];
+        for value in [
+            signature_offset as u16,
+            instruction_index,
+            public_key_offset as u16,
+            instruction_index,
+            message_offset as u16,
+            message.len() as u16,
+            instruction_index,
+        ]
+        .iter()
+        {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(signer.as_ref());
+        data.extend_from_slice(&[0u8; 64]);
+        data.extend_from_slice(message);
+        data
+    }
+
+    /// Instructions sysvar data for a transaction made of `instructions`.
+    fn instructions_sysvar(instructions: &[(Pubkey, Vec<u8>)]) -> Vec<u8> {
+        let instructions: Vec<Instruction> = instructions
+            .iter()
+            .map(|(program_id, data)| Instruction {
+                program_id: *program_id,
+                accounts: vec![],
+                data: data.clone(),
+            })
+            .collect();
+        Message::new(&instructions, None).serialize_instructions()
+    }
+
+    #[test]
+    fn ed25519_message_is_read_from_the_signature_instruction_only() {
+        let cosigner = Pubkey::new_unique();
+        let message = [7u8; COSIGN_MESSAGE_LEN];
+        let sysvar = instructions_sysvar(&[
+            (
+                ED25519_PROGRAM_ID,
+                ed25519_data(&cosigner, &message, u16::MAX),
+            ),
+            (Pubkey::new_unique(), vec![]),
+        ]);
+        assert_eq!(
+            get_ed25519_message(&sysvar, 0, &cosigner),
+            Ok(message.to_vec())
+        );
+        assert_eq!(
+            get_ed25519_message(&sysvar, 0, &Pubkey::new_unique()),
+            Err(ErrorCode::CosignatureInvalid.into())
+        );
+        assert_eq!(
+            get_ed25519_message(&sysvar, 1, &cosigner),
+            Err(ErrorCode::CosignatureMissing.into())
+        );
+        assert_eq!(
+            get_ed25519_message(&sysvar, 2, &cosigner),
+            Err(ErrorCode::CosignatureMissing.into())
+        );
+    }
+
+    #[test]
+    fn ed25519_offsets_must_stay_in_the_signature_instruction() {
+        let cosigner = Pubkey::new_unique();
+        let message = [7u8; COSIGN_MESSAGE_LEN];
+
+        // Signature, key or message taken from another instruction could be anything
+        for index in [0u16, 1].iter() {
+            let sysvar = instructions_sysvar(&[
+                (
+                    ED25519_PROGRAM_ID,
+                    ed25519_data(&cosigner, &message, *index),
+                ),
+                (Pubkey::new_unique(), vec![]),
+            ]);
+            assert_eq!(
+                get_ed25519_message(&sysvar, 0, &cosigner),
+                Err(ErrorCode::CosignatureInvalid.into())
+            );
+        }
+
+        let mut data = ed25519_data(&cosigner, &message, u16::MAX);
+        // Message running past the instruction data
+        data[2 + 10..2 + 12].copy_from_slice(&(COSIGN_MESSAGE_LEN as u16 + 1).to_le_bytes());
+        let sysvar = instructions_sysvar(&[(ED25519_PROGRAM_ID, data)]);
+        assert_eq!(
+            get_ed25519_message(&sysvar, 0, &cosigner),
+            Err(ErrorCode::CosignatureInvalid.into())
+        );
+
+        // Zero or several signatures
+        for count in [0u8, 2].iter() {
+            let mut data = ed25519_data(&cosigner, &message, u16::MAX);
+            data[0] = *count;
+            let sysvar = instructions_sysvar(&[(ED25519_PROGRAM_ID, data)]);
+            assert_eq!(
+                get_ed25519_message(&sysvar, 0, &cosigner),
+                Err(ErrorCode::CosignatureInvalid.into())
+            );
+        }
+
+        // Offsets cut short
+        let sysvar = instructions_sysvar(&[(ED25519_PROGRAM_ID, vec![1u8, 0, 0, 0])]);
+        assert_eq!(
+            get_ed25519_message(&sysvar, 0, &cosigner),
+            Err(ErrorCode::CosignatureInvalid.into())
+        );
+    }
+
+    #[test]
+    fn compact_lines_rebuild_name_and_uri() {
+        let compact = CompactConfig {
+            uri_prefix: "https://arweave.net/".to_string(),
+            name_template: "Drop #{}".to_string(),
+            suffix_length: 12,
+        };
+        let line_size = get_config_line_size(&Some(compact.clone()));
+        let mut data = 2u32.to_le_bytes().to_vec();
+        // add_config_lines pads each suffix with zero chars to suffix_length
+        for suffix in ["abc.json", "defgh.json"].iter() {
+            let padded = format!("{:\0<12}", suffix);
+            data.extend_from_slice(&padded.try_to_vec().unwrap());
+        }
+
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        // A page holding config lines 10 and up
+        let segment = ConfigSegment {
+            account: data_account(&key, &mut lamports, &mut data),
+            array_start: 0,
+            start_index: 10,
+            max_number_of_lines: 2,
+            line_size,
+            compact: Some(compact),
+        };
+        let line = get_config_line(&segment, 10).unwrap();
+        assert_eq!(line.name, "Drop #11");
+        assert_eq!(line.uri, "https://arweave.net/abc.json");
+        let line = get_config_line(&segment, 11).unwrap();
+        assert_eq!(line.name, "Drop #12");
+        assert_eq!(line.uri, "https://arweave.net/defgh.json");
+    }
+
+    fn phase(start_time: i64, price: u64, max_per_wallet: Option<u64>) -> SalePhase {
+        SalePhase {
+            start_time,
+            price,
+            allowlist_root: None,
+            max_per_wallet,
+        }
+    }
+
+    #[test]
+    fn sale_phases_must_be_ordered_and_exclude_dutch_auctions() {
+        let mut data = NftVaultData {
+            phases: vec![phase(100, 1, None), phase(200, 2, None)],
+            ..NftVaultData::default()
+        };
+        assert_eq!(assert_valid_phases(&data), Ok(()));
+
+        data.phases[1].start_time = 100;
+        assert_eq!(
+            assert_valid_phases(&data),
+            Err(ErrorCode::InvalidSalePhases.into())
+        );
+
+        data.phases = (0..MAX_SALE_PHASES as i64 + 1)
+            .map(|i| phase(i, 1, None))
+            .collect();
+        assert_eq!(
+            assert_valid_phases(&data),
+            Err(ErrorCode::InvalidSalePhases.into())
+        );
+
+        data.phases = vec![phase(100, 1, None)];
+        data.dutch_auction = Some(DutchAuction::default());
+        assert_eq!(
+            assert_valid_phases(&data),
+            Err(ErrorCode::InvalidSalePhases.into())
+        );
+    }
+
+    #[test]
+    fn active_phase_sets_price_and_wallet_cap() {
+        let data = NftVaultData {
+            price: 9,
+            max_per_wallet: Some(50),
+            phases: vec![phase(100, 1, Some(2)), phase(200, 5, None)],
+            ..NftVaultData::default()
+        };
+        // Before the first phase nobody mints, see assert_mint_allowed
+        assert_eq!(get_wallet_mint_limit(&data, 99), None);
+        assert_eq!(get_mint_price(&data, 99), Ok(9));
+
+        assert_eq!(get_wallet_mint_limit(&data, 100), Some((2, Some(100))));
+        assert_eq!(get_mint_price(&data, 199), Ok(1));
+        // The vault wide cap does not apply once phases are set
+        assert_eq!(get_wallet_mint_limit(&data, 200), None);
+        assert_eq!(get_mint_price(&data, 200), Ok(5));
+
+        let no_phases = NftVaultData {
+            max_per_wallet: Some(50),
+            ..NftVaultData::default()
+        };
+        assert_eq!(get_wallet_mint_limit(&no_phases, 0), Some((50, None)));
+    }
 }
@@ -1,12 +1,20 @@
 use {
     crate::ErrorCode,
     anchor_lang::{
-        prelude::{AccountInfo, ProgramError, ProgramResult, Pubkey},
+        prelude::{msg, AccountInfo, ProgramError, ProgramResult, Pubkey},
         solana_program::{
-            program::invoke_signed,
+            instruction::{AccountMeta, Instruction},
+            keccak,
+            program::{invoke, invoke_signed},
+            program_memory::sol_memset,
             program_pack::{IsInitialized, Pack},
+            system_instruction,
+            sysvar::{instructions::load_instruction_at, rent::Rent, Sysvar},
         },
     },
+    spl_token::state::Account,
+    spl_token_metadata::state::Metadata,
+    std::convert::TryInto,
 };
 
 pub fn assert_initialized<T: Pack + IsInitialized>(
@@ -36,6 +44,42 @@ pub struct TokenTransferParams<'a: 'b, 'b> {
     pub token_program: AccountInfo<'a>,
 }
 
+pub struct TokenMintToParams<'a: 'b, 'b> {
+    pub mint: AccountInfo<'a>,
+    pub destination: AccountInfo<'a>,
+    pub amount: u64,
+    pub authority: AccountInfo<'a>,
+    pub authority_signer_seeds: &'b [&'b [u8]],
+    pub token_program: AccountInfo<'a>,
+}
+
+#[inline(always)]
+pub fn spl_token_mint_to(params: TokenMintToParams<'_, '_>) -> ProgramResult {
+    let TokenMintToParams {
+        mint,
+        destination,
+        authority,
+        token_program,
+        amount,
+        authority_signer_seeds,
+    } = params;
+
+    let result = invoke_signed(
+        &spl_token::instruction::mint_to(
+            token_program.key,
+            mint.key,
+            destination.key,
+            authority.key,
+            &[],
+            amount,
+        )?,
+        &[mint, destination, authority, token_program],
+        &[authority_signer_seeds],
+    );
+
+    result.map_err(|_| ErrorCode::TokenMintToFailed.into())
+}
+
 #[inline(always)]
 pub fn spl_token_transfer(params: TokenTransferParams<'_, '_>) -> ProgramResult {
     let TokenTransferParams {
@@ -62,3 +106,309 @@ pub fn spl_token_transfer(params: TokenTransferParams<'_, '_>) -> ProgramResult
 
     result.map_err(|_| ErrorCode::TokenTransferFailed.into())
 }
+
+pub fn verify_merkle_proof(proof: &[[u8; 32]], root: [u8; 32], leaf: [u8; 32]) -> bool {
+    let mut computed_hash = leaf;
+    for proof_element in proof {
+        if computed_hash <= *proof_element {
+            computed_hash = keccak::hashv(&[&computed_hash, proof_element]).0;
+        } else {
+            computed_hash = keccak::hashv(&[proof_element, &computed_hash]).0;
+        }
+    }
+    computed_hash == root
+}
+
+pub fn assert_allowlisted(
+    root: Option<[u8; 32]>,
+    proof: &Option<Vec<[u8; 32]>>,
+    wallet: &Pubkey,
+) -> ProgramResult {
+    match (root, proof) {
+        (Some(root), Some(proof)) => {
+            let leaf = keccak::hashv(&[wallet.as_ref()]).0;
+            if verify_merkle_proof(proof, root, leaf) {
+                Ok(())
+            } else {
+                Err(ErrorCode::InvalidAllowlistProof.into())
+            }
+        }
+        _ => Err(ErrorCode::NftVaultNotLiveYet.into()),
+    }
+}
+
+#[inline(always)]
+pub fn create_or_allocate_account_raw<'a>(
+    program_id: Pubkey,
+    new_account_info: &AccountInfo<'a>,
+    rent_sysvar_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+    payer_info: &AccountInfo<'a>,
+    size: usize,
+    signer_seeds: &[&[u8]],
+) -> ProgramResult {
+    let rent = &Rent::from_account_info(rent_sysvar_info)?;
+    let required_lamports = rent
+        .minimum_balance(size)
+        .max(1)
+        .saturating_sub(new_account_info.lamports());
+
+    if required_lamports > 0 {
+        msg!("Transfer {} lamports to the new account", required_lamports);
+        invoke(
+            &system_instruction::transfer(payer_info.key, new_account_info.key, required_lamports),
+            &[
+                payer_info.clone(),
+                new_account_info.clone(),
+                system_program_info.clone(),
+            ],
+        )?;
+    }
+
+    let accounts = &[new_account_info.clone(), system_program_info.clone()];
+
+    msg!("Allocate space for the account");
+    invoke_signed(
+        &system_instruction::allocate(new_account_info.key, size.try_into().unwrap()),
+        accounts,
+        &[signer_seeds],
+    )?;
+
+    msg!("Assign the account to the owning program");
+    invoke_signed(
+        &system_instruction::assign(new_account_info.key, &program_id),
+        accounts,
+        &[signer_seeds],
+    )?;
+
+    Ok(())
+}
+
+pub fn assert_holds_verified_nft(
+    holder: &Pubkey,
+    token_account_info: &AccountInfo,
+    metadata_info: &AccountInfo,
+    creator: &Pubkey,
+) -> Result<Account, ProgramError> {
+    assert_owned_by(token_account_info, &spl_token::id())?;
+    assert_owned_by(metadata_info, &spl_token_metadata::id())?;
+
+    let token_account: Account = assert_initialized(token_account_info)?;
+    if token_account.owner != *holder || token_account.amount < 1 {
+        return Err(ErrorCode::NftNotVerifiedByCreator.into());
+    }
+
+    let metadata = Metadata::from_account_info(metadata_info)?;
+    if metadata.mint != token_account.mint {
+        return Err(ErrorCode::NftNotVerifiedByCreator.into());
+    }
+
+    let verified_by_creator = match &metadata.data.creators {
+        Some(creators) => creators.iter().any(|c| c.address == *creator && c.verified),
+        None => false,
+    };
+    if !verified_by_creator {
+        return Err(ErrorCode::NftNotVerifiedByCreator.into());
+    }
+
+    Ok(token_account)
+}
+
+pub struct TokenBurnParams<'a: 'b, 'b> {
+    pub mint: AccountInfo<'a>,
+    pub source: AccountInfo<'a>,
+    pub amount: u64,
+    pub authority: AccountInfo<'a>,
+    pub authority_signer_seeds: &'b [&'b [u8]],
+    pub token_program: AccountInfo<'a>,
+}
+
+#[inline(always)]
+pub fn spl_token_burn(params: TokenBurnParams<'_, '_>) -> ProgramResult {
+    let TokenBurnParams {
+        mint,
+        source,
+        authority,
+        token_program,
+        amount,
+        authority_signer_seeds,
+    } = params;
+
+    let result = invoke_signed(
+        &spl_token::instruction::burn(
+            token_program.key,
+            source.key,
+            mint.key,
+            authority.key,
+            &[],
+            amount,
+        )?,
+        &[source, mint, authority, token_program],
+        &[authority_signer_seeds],
+    );
+
+    result.map_err(|_| ErrorCode::TokenBurnFailed.into())
+}
+
+pub struct TokenCloseParams<'a: 'b, 'b> {
+    pub account: AccountInfo<'a>,
+    pub destination: AccountInfo<'a>,
+    pub authority: AccountInfo<'a>,
+    pub authority_signer_seeds: &'b [&'b [u8]],
+    pub token_program: AccountInfo<'a>,
+}
+
+#[inline(always)]
+pub fn spl_token_close_account(params: TokenCloseParams<'_, '_>) -> ProgramResult {
+    let TokenCloseParams {
+        account,
+        destination,
+        authority,
+        token_program,
+        authority_signer_seeds,
+    } = params;
+
+    let result = invoke_signed(
+        &spl_token::instruction::close_account(
+            token_program.key,
+            account.key,
+            destination.key,
+            authority.key,
+            &[],
+        )?,
+        &[account, destination, authority, token_program],
+        &[authority_signer_seeds],
+    );
+
+    result.map_err(|_| ErrorCode::TokenCloseFailed.into())
+}
+
+/// Moves every lamport of a program owned account to `destination` and zeroes its data.
+pub fn close_program_account(account: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+    let account_lamports = account.lamports();
+    **destination.lamports.borrow_mut() = destination
+        .lamports()
+        .checked_add(account_lamports)
+        .ok_or(ErrorCode::NumericalOverflowError)?;
+    **account.lamports.borrow_mut() = 0;
+
+    let mut data = account.data.borrow_mut();
+    let len = data.len();
+    sol_memset(&mut data, 0, len);
+
+    Ok(())
+}
+
+/// Ed25519SigVerify111111111111111111111111111
+pub const ED25519_PROGRAM_ID: Pubkey = Pubkey::new_from_array([
+    3, 125, 70, 214, 124, 147, 251, 190, 18, 249, 66, 143, 131, 141, 64, 255, 5, 112, 116, 73, 39,
+    244, 138, 100, 252, 202, 112, 68, 128, 0, 0, 0,
+]);
+
+/// Offsets header of an ed25519 program instruction, after its signature count and padding.
+const ED25519_OFFSETS_START: usize = 2;
+const ED25519_OFFSETS_SIZE: usize = 14;
+
+fn read_u16(data: &[u8], offset: usize) -> Result<usize, ProgramError> {
+    match data.get(offset..offset + 2) {
+        Some(bytes) => Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as usize),
+        None => Err(ErrorCode::CosignatureInvalid.into()),
+    }
+}
+
+/// Returns the message verified by the ed25519 program instruction at `index` in the
+/// instructions sysvar. The instruction must hold a single signature by `signer` whose
+/// signature, key and message all live in that same instruction.
+pub fn get_ed25519_message(
+    instruction_sysvar: &[u8],
+    index: usize,
+    signer: &Pubkey,
+) -> Result<Vec<u8>, ProgramError> {
+    let instruction = load_instruction_at(index, instruction_sysvar)
+        .map_err(|_| ErrorCode::CosignatureMissing)?;
+    if instruction.program_id != ED25519_PROGRAM_ID {
+        return Err(ErrorCode::CosignatureMissing.into());
+    }
+
+    let data = &instruction.data;
+    if data.first() != Some(&1) {
+        return Err(ErrorCode::CosignatureInvalid.into());
+    }
+    let offsets = ED25519_OFFSETS_START;
+    let signature_instruction_index = read_u16(data, offsets + 2)?;
+    let public_key_offset = read_u16(data, offsets + 4)?;
+    let public_key_instruction_index = read_u16(data, offsets + 6)?;
+    let message_offset = read_u16(data, offsets + 8)?;
+    let message_size = read_u16(data, offsets + 10)?;
+    let message_instruction_index = read_u16(data, offsets + 12)?;
+    if data.len() < offsets + ED25519_OFFSETS_SIZE
+        || signature_instruction_index != u16::MAX as usize
+        || public_key_instruction_index != u16::MAX as usize
+        || message_instruction_index != u16::MAX as usize
+    {
+        return Err(ErrorCode::CosignatureInvalid.into());
+    }
+
+    match (
+        data.get(public_key_offset..public_key_offset + 32),
+        data.get(message_offset..message_offset + message_size),
+    ) {
+        (Some(public_key), Some(message)) if public_key == signer.as_ref() => Ok(message.to_vec()),
+        _ => Err(ErrorCode::CosignatureInvalid.into()),
+    }
+}
+
+/// ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey = Pubkey::new_from_array([
+    140, 151, 37, 143, 78, 36, 137, 241, 187, 61, 16, 41, 20, 142, 13, 131, 11, 90, 19, 153, 218,
+    255, 16, 132, 4, 142, 123, 216, 219, 233, 248, 89,
+]);
+
+pub fn get_associated_token_address(wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), spl_token::id().as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    )
+    .0
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_associated_token_account<'a>(
+    payer: AccountInfo<'a>,
+    associated_token_account: AccountInfo<'a>,
+    wallet: AccountInfo<'a>,
+    mint: AccountInfo<'a>,
+    system_program: AccountInfo<'a>,
+    token_program: AccountInfo<'a>,
+    associated_token_program: AccountInfo<'a>,
+    rent: AccountInfo<'a>,
+) -> ProgramResult {
+    let instruction = Instruction {
+        program_id: ASSOCIATED_TOKEN_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*payer.key, true),
+            AccountMeta::new(*associated_token_account.key, false),
+            AccountMeta::new_readonly(*wallet.key, false),
+            AccountMeta::new_readonly(*mint.key, false),
+            AccountMeta::new_readonly(*system_program.key, false),
+            AccountMeta::new_readonly(*token_program.key, false),
+            AccountMeta::new_readonly(*rent.key, false),
+        ],
+        data: vec![],
+    };
+
+    invoke(
+        &instruction,
+        &[
+            payer,
+            associated_token_account,
+            wallet,
+            mint,
+            system_program,
+            token_program,
+            associated_token_program,
+            rent,
+        ],
+    )
+    .map_err(|_| ErrorCode::AssociatedTokenAccountCreationFailed.into())
+}